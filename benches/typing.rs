@@ -0,0 +1,91 @@
+//! Typing into the middle of a 50 MB file, first through the same `Document` calls the editor
+//! widget makes, then through whole editor frames, which add input handling, layout and
+//! highlighting of the visible lines.
+//!
+//! Run with `cargo bench --bench typing`.
+
+use clawpad::document::Document;
+use clawpad::editor::EditorView;
+use clawpad::syntax::SyntaxHighlighter;
+use eframe::egui;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const TARGET_SIZE: usize = 50 * 1024 * 1024;
+const FRAME_BUDGET: Duration = Duration::from_micros(16_600);
+const TYPED: &str = "the quick brown fox jumps over the lazy dog";
+
+fn main() {
+    let line = "2024-05-24T12:00:00.000Z INFO  clawpad::worker: request handled in 12ms\n";
+    let mut doc = Document::new(line.repeat(TARGET_SIZE / line.len()), None);
+    let middle = doc.rope.len_chars() / 2;
+    doc.set_cursors(vec![(middle, middle)], 0);
+
+    let mut timings = Vec::new();
+    for ch in TYPED.chars() {
+        let start = Instant::now();
+        let before = doc.cursor_state();
+        doc.replace_selection(ch.encode_utf8(&mut [0; 4]));
+        doc.commit_edits(before);
+        timings.push(start.elapsed());
+    }
+    for _ in 0..10 {
+        let start = Instant::now();
+        let before = doc.cursor_state();
+        let (_, head) = doc.selection();
+        doc.set_cursors(vec![(head - 1, head)], 0);
+        doc.replace_selection("");
+        doc.commit_edits(before);
+        timings.push(start.elapsed());
+    }
+    report("keystrokes", &timings, &doc);
+
+    let function = "fn handle(request: &Request) -> Result<Response, Error> {\n    let body = request.body()?;\n    Ok(Response::new(200, body.len()))\n}\n\n";
+    let mut doc = Document::new(function.repeat(TARGET_SIZE / function.len()), Some(PathBuf::from("typing.rs")));
+    let timings = type_in_editor(&mut doc);
+    report("editor frames", &timings, &doc);
+}
+
+/// Runs one editor frame per typed character, after a few to lay out and focus the editor.
+fn type_in_editor(doc: &mut Document) -> Vec<Duration> {
+    let ctx = egui::Context::default();
+    let highlighter = SyntaxHighlighter::new();
+    let middle = doc.rope.line_to_char(doc.rope.len_lines() / 2);
+    doc.set_cursors(vec![(middle, middle)], 0);
+    doc.reveal_cursor = true;
+
+    let mut frame = |events: Vec<egui::Event>| {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(1280.0, 800.0))),
+            events,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let _ = ctx.run(input, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                EditorView::new(doc, &highlighter, "base16-ocean.dark", egui::FontId::monospace(14.0)).show(ui);
+            });
+        });
+        start.elapsed()
+    };
+    for _ in 0..3 {
+        frame(Vec::new());
+    }
+    let timings = TYPED.chars().map(|ch| frame(vec![egui::Event::Text(ch.to_string())])).collect();
+    assert!(doc.is_dirty, "the editor didn't take the typed text");
+    timings
+}
+
+fn report(what: &str, timings: &[Duration], doc: &Document) {
+    let worst = timings.iter().max().copied().unwrap_or_default();
+    let mean = timings.iter().sum::<Duration>() / timings.len() as u32;
+    println!(
+        "{} {what} into {} MB: mean {:?}, worst {:?} (budget {:?})",
+        timings.len(),
+        doc.rope.len_bytes() / (1024 * 1024),
+        mean,
+        worst,
+        FRAME_BUDGET
+    );
+    assert!(worst < FRAME_BUDGET, "{what}: {worst:?}, over the frame budget");
+}
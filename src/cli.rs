@@ -0,0 +1,143 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A lightweight, fast text editor.
+#[derive(Parser)]
+#[command(name = "clawpad", version)]
+pub struct Args {
+    /// Files to open, optionally as FILE:LINE[:COLUMN], folders to open as the workspace,
+    /// or `-` to read standard input into an untitled tab
+    pub paths: Vec<String>,
+    /// Start a separate instance instead of opening the files in the running one
+    #[arg(long, visible_alias = "new-window")]
+    pub new_instance: bool,
+    /// Wait until the opened files' tabs are closed before exiting, e.g. as GIT_EDITOR
+    #[arg(long)]
+    pub wait: bool,
+    /// Compare two files
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    pub diff: Option<Vec<PathBuf>>,
+}
+
+/// Something to open from the command line.
+#[derive(Serialize, Deserialize)]
+pub enum Target {
+    File { path: PathBuf, line: Option<usize>, column: Option<usize> },
+    Folder(PathBuf),
+    Stdin(String),
+}
+
+/// Everything an invocation asks to open, with paths made absolute so another instance can
+/// open them too.
+#[derive(Serialize, Deserialize)]
+pub struct OpenRequest {
+    pub targets: Vec<Target>,
+    pub diff: Option<Vec<PathBuf>>,
+    pub wait: bool,
+}
+
+impl OpenRequest {
+    pub fn has_folders(&self) -> bool {
+        !self.folders().is_empty()
+    }
+
+    pub fn folders(&self) -> Vec<PathBuf> {
+        self.targets
+            .iter()
+            .filter_map(|target| match target {
+                Target::Folder(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Args {
+    pub fn request(&self) -> OpenRequest {
+        let diff = self.diff.as_ref().map(|paths| paths.iter().map(|path| absolute(path)).collect());
+        OpenRequest { targets: self.targets(), diff, wait: self.wait }
+    }
+
+    /// What the paths refer to, with standard input read if it was asked for.
+    fn targets(&self) -> Vec<Target> {
+        self.paths
+            .iter()
+            .map(|arg| {
+                if arg == "-" {
+                    let mut text = String::new();
+                    let _ = std::io::stdin().read_to_string(&mut text);
+                    return Target::Stdin(text);
+                }
+                let (path, line, column) = parse_location(arg);
+                let path = absolute(&path);
+                if path.is_dir() {
+                    Target::Folder(path)
+                } else {
+                    Target::File { path, line, column }
+                }
+            })
+            .collect()
+    }
+}
+
+// Splits "file:line:column" or "file:line", unless the whole argument names an existing file
+fn parse_location(arg: &str) -> (PathBuf, Option<usize>, Option<usize>) {
+    if Path::new(arg).exists() {
+        return (PathBuf::from(arg), None, None);
+    }
+    let number = |part: &str| part.parse::<usize>().ok().filter(|n| *n > 0);
+    let mut parts = arg.rsplitn(3, ':');
+    let (last, middle, rest) = (parts.next(), parts.next(), parts.next());
+    match (last.and_then(number), middle.and_then(number), rest) {
+        (Some(column), Some(line), Some(path)) => (PathBuf::from(path), Some(line), Some(column)),
+        (Some(line), _, _) => {
+            let path = &arg[..arg.len() - last.unwrap_or_default().len() - 1];
+            (PathBuf::from(path), Some(line), None)
+        }
+        _ => (PathBuf::from(arg), None, None),
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_location_splits_line_and_column() {
+        assert_eq!(parse_location("missing.rs:12:5"), (PathBuf::from("missing.rs"), Some(12), Some(5)));
+        assert_eq!(parse_location("missing.rs:12"), (PathBuf::from("missing.rs"), Some(12), None));
+        assert_eq!(parse_location("missing.rs"), (PathBuf::from("missing.rs"), None, None));
+    }
+
+    #[test]
+    fn parse_location_keeps_colons_that_are_not_numbers() {
+        assert_eq!(parse_location("a:b.rs:3"), (PathBuf::from("a:b.rs"), Some(3), None));
+        assert_eq!(parse_location("a:b:c"), (PathBuf::from("a:b:c"), None, None));
+        assert_eq!(parse_location("missing.rs:x:5"), (PathBuf::from("missing.rs:x"), Some(5), None));
+    }
+
+    #[test]
+    fn parse_location_rejects_zero_and_empty_positions() {
+        assert_eq!(parse_location("missing.rs:0"), (PathBuf::from("missing.rs:0"), None, None));
+        assert_eq!(parse_location("missing.rs:0:3"), (PathBuf::from("missing.rs:0"), Some(3), None));
+        assert_eq!(parse_location("missing.rs:"), (PathBuf::from("missing.rs:"), None, None));
+        assert_eq!(parse_location(""), (PathBuf::new(), None, None));
+    }
+
+    #[test]
+    fn parse_location_prefers_an_existing_file() {
+        let dir = std::env::temp_dir().join(format!("clawpad-cli-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes:12");
+        std::fs::write(&file, "").unwrap();
+        let arg = file.to_str().unwrap();
+        assert_eq!(parse_location(arg), (file, None, None));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+use crate::encoding;
+use egui::{Color32, RichText, Ui};
+use std::path::Path;
+
+/// Unified diff from `old` to `new`, with the names shown in its header.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Draws a unified diff with added and removed lines colored.
+pub fn show_lines(ui: &mut Ui, diff: &str) {
+    for line in diff.lines() {
+        let color = match line.chars().next() {
+            Some('+') => Color32::from_rgb(80, 180, 80),
+            Some('-') => ui.visuals().error_fg_color,
+            Some('@') => ui.visuals().weak_text_color(),
+            _ => ui.visuals().text_color(),
+        };
+        ui.label(RichText::new(line).monospace().color(color));
+    }
+}
+
+/// A window comparing two files.
+pub struct DiffView {
+    title: String,
+    diff: String,
+    pub open: bool,
+}
+
+impl DiffView {
+    pub fn files(a: &Path, b: &Path) -> Self {
+        let read = |path: &Path| {
+            encoding::read(path).map(|decoded| decoded.text).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))
+        };
+        let diff = match (read(a), read(b)) {
+            (Ok(old), Ok(new)) if old == new => "The files are identical.".to_string(),
+            (Ok(old), Ok(new)) => unified(&old, &new, &a.display().to_string(), &b.display().to_string()),
+            (Err(error), _) | (_, Err(error)) => error,
+        };
+        Self {
+            title: format!("{} ↔ {}", file_name(a), file_name(b)),
+            diff,
+            open: true,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        egui::Window::new(&self.title)
+            .open(&mut self.open)
+            .default_size([700.0, 500.0])
+            .show(ctx, |ui| {
+                egui::ScrollArea::both().auto_shrink(false).show(ui, |ui| show_lines(ui, &self.diff));
+            });
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string()
+}
@@ -1,42 +1,115 @@
+use crate::encoding::TextEncoding;
+use crate::history::{Cursors, EditOp, History, Selection};
+use crate::line_ending::{self, LineEnding};
+use crate::save;
+use crate::syntax::HighlightCache;
 use ropey::Rope;
-use std::path::PathBuf;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Hashes the bytes of `chunks` as one string, however it is split
+fn content_hash<'a>(chunks: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for chunk in chunks {
+        hasher.write(chunk.as_bytes());
+    }
+    hasher.finish()
+}
+
+fn language_for(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("rs") => "Rust",
+        Some("py") => "Python",
+        Some("md") => "Markdown",
+        _ => "Plain Text",
+    }
+}
 
 pub struct Document {
+    pub id: u64,
     pub rope: Rope,
     pub file_path: Option<PathBuf>,
     pub is_dirty: bool,
+    // The file changed on disk while the document had unsaved changes
+    pub disk_changed: bool,
+    // Hash of the file's contents as last loaded or saved, to tell our own writes from others'
+    // and whether undo got back to them; None once the text can't be back in sync by undoing
+    disk_hash: Option<u64>,
     pub language: String,
-    // Buffer for egui::TextEdit
-    pub content_cache: String,
-    #[allow(dead_code)]
-    pub cursors: Vec<usize>, 
+    pub encoding: TextEncoding,
+    // What `\n` becomes when saving; the text itself never holds `\r\n`
+    pub line_ending: LineEnding,
+    // The file had both LF and CRLF line breaks; saving makes them all `line_ending`
+    pub mixed_line_endings: bool,
+    // Some bytes of the file weren't valid in `encoding` and show as U+FFFD, which saving writes
+    // in their place; cleared once the user has been warned
+    pub invalid_bytes: bool,
+    // Derived from the rope, see `text()`
+    content_cache: String,
+    cache_stale: bool,
+    // Bumped on every change to the rope
+    revision: u64,
+    // Every caret/selection, sorted and non-overlapping; never empty
+    pub cursors: Vec<Selection>,
+    // The cursor the view follows, usually the most recently added one
+    pub primary: usize,
+    pub scroll_offset: egui::Vec2,
+    // Set when the selection moves outside the editor (undo, Ctrl+D) so the view follows it
+    pub reveal_cursor: bool,
+    pub history: History,
+    pending_ops: Vec<EditOp>,
+    // Shared with the background highlighter
+    pub highlight: Arc<Mutex<HighlightCache>>,
 }
 
 impl Document {
+    /// A document holding `content`, with its line breaks turned into `\n`. Text without line
+    /// breaks gets LF.
     pub fn new(content: String, path: Option<PathBuf>) -> Self {
-        let language = if let Some(p) = &path {
-            match p.extension().and_then(|e| e.to_str()) {
-                Some("rs") => "Rust",
-                Some("py") => "Python",
-                Some("md") => "Markdown",
-                _ => "Plain Text",
-            }
+        let language = path.as_deref().map_or("Plain Text", language_for).to_string();
+        let detected = line_ending::detect(&content);
+        let content = if detected.ending == Some(LineEnding::CrLf) || detected.mixed {
+            line_ending::normalize(&content).into_owned()
         } else {
-            "Plain Text"
-        }.to_string();
+            content
+        };
 
         Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             rope: Rope::from_str(&content),
             file_path: path,
             is_dirty: false,
+            disk_changed: false,
+            disk_hash: Some(content_hash([content.as_str()])),
             language,
+            encoding: TextEncoding::UTF8,
+            line_ending: detected.ending.unwrap_or(LineEnding::Lf),
+            mixed_line_endings: detected.mixed,
+            invalid_bytes: false,
             content_cache: content,
-            cursors: Vec::new(),
+            cache_stale: false,
+            revision: 0,
+            cursors: vec![(0, 0)],
+            primary: 0,
+            scroll_offset: egui::Vec2::ZERO,
+            reveal_cursor: false,
+            history: History::default(),
+            pending_ops: Vec::new(),
+            highlight: Arc::default(),
         }
     }
 
-    pub fn untitled() -> Self {
-        Self::new(String::new(), None)
+    /// Points the document at another file, e.g. after Save As or a rename.
+    pub fn set_file_path(&mut self, path: PathBuf) {
+        self.language = language_for(&path).to_string();
+        self.file_path = Some(path);
     }
 
     pub fn name(&self) -> String {
@@ -47,10 +120,512 @@ impl Document {
             .unwrap_or_else(|| "Untitled".to_string())
     }
 
-    pub fn sync_from_cache(&mut self) {
-        if self.rope != self.content_cache {
-            self.rope = Rope::from_str(&self.content_cache);
-            self.is_dirty = true;
+    /// Flattened text of the rope, rebuilt on demand after the document changed.
+    pub fn text(&mut self) -> &str {
+        if self.cache_stale {
+            self.content_cache = self.rope.to_string();
+            self.cache_stale = false;
+        }
+        &self.content_cache
+    }
+
+    /// Changes whenever the text does, for caches derived from it.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Text of a line without its line break.
+    pub fn line_text(&self, line: usize) -> String {
+        let mut text = self.rope.line(line).to_string();
+        while text.ends_with(['\n', '\r']) {
+            text.pop();
+        }
+        text
+    }
+
+    /// Number of chars in a line, excluding its line break.
+    pub fn line_len(&self, line: usize) -> usize {
+        let slice = self.rope.line(line);
+        let mut len = slice.len_chars();
+        while len > 0 && matches!(slice.char(len - 1), '\n' | '\r') {
+            len -= 1;
+        }
+        len
+    }
+
+    /// The primary selection.
+    pub fn selection(&self) -> Selection {
+        self.cursors[self.primary]
+    }
+
+    pub fn selected_range(&self) -> Range<usize> {
+        let (anchor, head) = self.selection();
+        anchor.min(head)..anchor.max(head)
+    }
+
+    /// Text of every selection, one per line.
+    pub fn selected_text(&self) -> String {
+        self.cursors.iter()
+            .map(|&(anchor, head)| self.rope.slice(anchor.min(head)..anchor.max(head)).to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Collapses to a single selection and scrolls it into view.
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.set_cursors(vec![selection], 0);
+        self.reveal_cursor = true;
+    }
+
+    /// Replaces all cursors, merging the ones that overlap. `primary` indexes into `cursors`.
+    pub fn set_cursors(&mut self, cursors: Vec<Selection>, primary: usize) {
+        let len = self.rope.len_chars();
+        let mut cursors: Vec<(Selection, bool)> = cursors.into_iter()
+            .enumerate()
+            .map(|(i, (anchor, head))| ((anchor.min(len), head.min(len)), i == primary))
+            .collect();
+        cursors.sort_by_key(|&((anchor, head), _)| (anchor.min(head), anchor.max(head)));
+
+        let mut merged: Vec<(Selection, bool)> = Vec::with_capacity(cursors.len());
+        for ((anchor, head), is_primary) in cursors {
+            if let Some(((last_anchor, last_head), last_primary)) = merged.last_mut() {
+                let last_end = (*last_anchor).max(*last_head);
+                let empty = anchor == head || last_anchor == last_head;
+                if anchor.min(head) < last_end || (anchor.min(head) == last_end && empty) {
+                    let start = (*last_anchor).min(*last_head);
+                    let end = last_end.max(anchor.max(head));
+                    (*last_anchor, *last_head) = if last_anchor <= last_head { (start, end) } else { (end, start) };
+                    *last_primary |= is_primary;
+                    continue;
+                }
+            }
+            merged.push(((anchor, head), is_primary));
+        }
+
+        self.primary = merged.iter().position(|(_, is_primary)| *is_primary).unwrap_or(merged.len() - 1);
+        self.cursors = merged.into_iter().map(|(selection, _)| selection).collect();
+    }
+
+    /// Puts a single caret at a zero-based line and column, clamped to the text.
+    pub fn go_to(&mut self, line: usize, column: usize) {
+        let line = line.min(self.rope.len_lines() - 1);
+        let at = self.rope.line_to_char(line) + column.min(self.line_len(line));
+        self.set_selection((at, at));
+    }
+
+    /// Adds a cursor and makes it the primary one.
+    pub fn add_cursor(&mut self, selection: Selection) {
+        let mut cursors = self.cursors.clone();
+        cursors.push(selection);
+        let primary = cursors.len() - 1;
+        self.set_cursors(cursors, primary);
+        self.reveal_cursor = true;
+    }
+
+    pub fn insert(&mut self, at: usize, text: &str) {
+        if !text.is_empty() {
+            self.apply(EditOp::Insert { at, text: text.to_string() });
+        }
+    }
+
+    pub fn delete(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.rope.len_chars());
+        if range.start < end {
+            let text = self.rope.slice(range.start..end).to_string();
+            self.apply(EditOp::Delete { at: range.start, text });
+        }
+    }
+
+    /// Replaces every selection with `text` and leaves the carets after it.
+    pub fn replace_selection(&mut self, text: &str) {
+        self.edit_cursors(|_, _, range| (range, text.to_string()));
+    }
+
+    /// Applies one edit per cursor, front to back. `edit` gets the cursor's index and its
+    /// selected range in the current text, and returns the range to replace and the replacement.
+    /// Each cursor ends up as a caret after its replacement.
+    pub fn edit_cursors(&mut self, mut edit: impl FnMut(&Document, usize, Range<usize>) -> (Range<usize>, String)) {
+        let cursors = self.cursors.clone();
+        let mut carets: Vec<usize> = Vec::with_capacity(cursors.len());
+        let mut delta = 0isize;
+        for (i, &(anchor, head)) in cursors.iter().enumerate() {
+            let shift = |index: usize| index.saturating_add_signed(delta);
+            let (range, text) = edit(self, i, shift(anchor.min(head))..shift(anchor.max(head)));
+            let range = range.start..range.end.min(self.rope.len_chars());
+            let inserted = text.chars().count();
+            self.delete(range.clone());
+            self.insert(range.start, &text);
+            delta += inserted as isize - range.len() as isize;
+
+            // An edit reaching back past earlier carets (e.g. word backspace) pulls them along
+            for caret in carets.iter_mut().rev().take_while(|caret| **caret > range.start) {
+                *caret = if *caret >= range.end { *caret + inserted - range.len() } else { range.start };
+            }
+            carets.push(range.start + inserted);
+        }
+        let primary = self.primary;
+        self.set_cursors(carets.into_iter().map(|caret| (caret, caret)).collect(), primary);
+    }
+
+    // Char ranges of `needle` in document order
+    fn occurrences(&mut self, needle: &str) -> Vec<Range<usize>> {
+        let len = needle.chars().count();
+        let bytes: Vec<usize> = self.text().match_indices(needle).map(|(byte, _)| byte).collect();
+        bytes.into_iter()
+            .map(|byte| {
+                let start = self.rope.byte_to_char(byte);
+                start..start + len
+            })
+            .collect()
+    }
+
+    /// Adds a cursor at the next occurrence of the primary selection that is not selected yet,
+    /// wrapping around.
+    pub fn add_next_occurrence(&mut self) {
+        let range = self.selected_range();
+        if range.is_empty() {
+            return;
+        }
+        let needle = self.rope.slice(range.clone()).to_string();
+        let occurrences = self.occurrences(&needle);
+        let split = occurrences.partition_point(|found| found.start < range.end);
+        let next = occurrences[split..].iter()
+            .chain(&occurrences[..split])
+            .find(|found| !self.cursors.iter().any(|&(anchor, head)| anchor.min(head) == found.start));
+        if let Some(found) = next {
+            self.add_cursor((found.start, found.end));
+        }
+    }
+
+    /// Selects every occurrence of the primary selection.
+    pub fn select_all_occurrences(&mut self) {
+        let range = self.selected_range();
+        if range.is_empty() {
+            return;
+        }
+        let needle = self.rope.slice(range.clone()).to_string();
+        let occurrences = self.occurrences(&needle);
+        let primary = occurrences.iter().position(|found| found.start == range.start).unwrap_or(0);
+        self.set_cursors(occurrences.into_iter().map(|found| (found.start, found.end)).collect(), primary);
+    }
+
+    /// Moves edits made since the last commit onto the undo stack as one step.
+    pub fn commit_edits(&mut self, cursors_before: Cursors) {
+        if self.pending_ops.is_empty() {
+            return;
+        }
+        let ops = std::mem::take(&mut self.pending_ops);
+        self.history.record(ops, cursors_before, self.cursor_state());
+        self.is_dirty = true;
+    }
+
+    /// The cursors as undo and redo put them back.
+    pub fn cursor_state(&self) -> Cursors {
+        (self.cursors.clone(), self.primary)
+    }
+
+    /// Replaces the text with `content` as one undoable step. Only the part between the common
+    /// prefix and suffix is replaced, so undo doesn't keep both texts whole, and cursors after it
+    /// move along with the text.
+    pub fn replace_text(&mut self, content: &str) {
+        let (old_len, new_len) = (self.rope.len_chars(), content.chars().count());
+        // (chars, bytes of `content`) on either side that stay the same
+        let mut prefix = (0, 0);
+        for (old, new) in self.rope.chars().zip(content.chars()) {
+            if old != new {
+                break;
+            }
+            prefix = (prefix.0 + 1, prefix.1 + new.len_utf8());
+        }
+        let mut suffix = (0, 0);
+        let reversed = self.rope.chars_at(old_len).reversed().zip(content.chars().rev());
+        for (old, new) in reversed.take(old_len.min(new_len) - prefix.0) {
+            if old != new {
+                break;
+            }
+            suffix = (suffix.0 + 1, suffix.1 + new.len_utf8());
+        }
+        let (removed, inserted) = (prefix.0..old_len - suffix.0, &content[prefix.1..content.len() - suffix.1]);
+        if removed.is_empty() && inserted.is_empty() {
+            return;
         }
+
+        let before = self.cursor_state();
+        let inserted_len = new_len - prefix.0 - suffix.0;
+        let moved = |index: usize| match index {
+            index if index >= removed.end => index - removed.len() + inserted_len,
+            index => index.min(removed.start + inserted_len),
+        };
+        let cursors = before.0.iter().map(|&(anchor, head)| (moved(anchor), moved(head))).collect();
+        self.history.break_group();
+        self.delete(removed.clone());
+        self.insert(removed.start, inserted);
+        self.set_cursors(cursors, before.1);
+        self.commit_edits(before);
+        self.history.break_group();
+    }
+
+    /// Replaces the text with the file's new contents, see `replace_text`.
+    pub fn reload(&mut self, content: &str) {
+        self.replace_text(&line_ending::normalize(content));
+        self.mark_on_disk(content);
+    }
+
+    /// Whether `content` is what the file held when it was last loaded or saved, line endings
+    /// aside.
+    pub fn is_on_disk(&self, content: &str) -> bool {
+        Some(content_hash([line_ending::normalize(content).as_ref()])) == self.disk_hash
+    }
+
+    /// Records that the file holds `content`, the document's own text, and takes on its line
+    /// endings.
+    pub fn mark_on_disk(&mut self, content: &str) {
+        let detected = line_ending::detect(content);
+        if let Some(ending) = detected.ending {
+            self.line_ending = ending;
+        }
+        self.mixed_line_endings = detected.mixed;
+        self.disk_hash = Some(content_hash([line_ending::normalize(content).as_ref()]));
+        self.is_dirty = false;
+        self.disk_changed = false;
+    }
+
+    /// Changes what line breaks are saved as; the file changes on the next save.
+    pub fn set_line_ending(&mut self, ending: LineEnding) {
+        if ending != self.line_ending || self.mixed_line_endings {
+            self.line_ending = ending;
+            self.mixed_line_endings = false;
+            self.mark_unsaved();
+        }
+    }
+
+    /// Marks the document unsaved in a way undo doesn't take back, e.g. after its file was
+    /// deleted.
+    pub fn mark_unsaved(&mut self) {
+        self.is_dirty = true;
+        self.disk_hash = None;
+    }
+
+    /// Writes the text to `path` in the document's encoding, atomically, see `save::write_atomic`.
+    pub fn save_to(&mut self, path: &Path, backup: bool) -> io::Result<()> {
+        if self.encoding == TextEncoding::UTF8 {
+            let ending = self.line_ending.as_str();
+            save::write_atomic(path, backup, |writer| {
+                for chunk in self.rope.chunks() {
+                    for (i, line) in chunk.split('\n').enumerate() {
+                        if i > 0 {
+                            writer.write_all(ending.as_bytes())?;
+                        }
+                        writer.write_all(line.as_bytes())?;
+                    }
+                }
+                Ok(())
+            })?;
+        } else {
+            let ending = self.line_ending;
+            let bytes = self.encoding.encode(&ending.apply(self.text()))?;
+            save::write_atomic(path, backup, |writer| writer.write_all(&bytes))?;
+        }
+        self.disk_hash = Some(content_hash(self.rope.chunks()));
+        self.mixed_line_endings = false;
+        Ok(())
+    }
+
+    fn apply(&mut self, op: EditOp) {
+        self.apply_to_rope(&op);
+        self.pending_ops.push(op);
+    }
+
+    // Every rope mutation goes through here so the derived caches follow along
+    fn apply_to_rope(&mut self, op: &EditOp) {
+        let (EditOp::Insert { at, .. } | EditOp::Delete { at, .. }) = op;
+        let start_line = self.rope.char_to_line(*at);
+        let lines_before = self.rope.len_lines();
+        op.apply(&mut self.rope);
+        let lines_after = self.rope.len_lines();
+
+        self.cache_stale = true;
+        self.revision += 1;
+        self.highlight.lock().unwrap_or_else(|e| e.into_inner()).edit(
+            start_line,
+            lines_before.saturating_sub(lines_after),
+            lines_after.saturating_sub(lines_before),
+        );
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((ops, cursors)) = self.history.undo() {
+            self.apply_history_step(&ops, cursors);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some((ops, cursors)) = self.history.redo() {
+            self.apply_history_step(&ops, cursors);
+        }
+    }
+
+    // Undoing back to the saved text makes the document clean again
+    fn apply_history_step(&mut self, ops: &[EditOp], (cursors, primary): Cursors) {
+        for op in ops {
+            self.apply_to_rope(op);
+        }
+        self.is_dirty = self.disk_hash != Some(content_hash(self.rope.chunks()));
+        self.set_cursors(cursors, primary);
+        self.reveal_cursor = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(doc: &mut Document, text: &str) {
+        let before = doc.cursor_state();
+        doc.history.break_group();
+        doc.replace_selection(text);
+        doc.commit_edits(before);
+    }
+
+    #[test]
+    fn undo_back_to_the_saved_text_is_clean() {
+        let mut doc = Document::new("abc".to_string(), None);
+        type_text(&mut doc, "x");
+        assert!(doc.is_dirty);
+        doc.undo();
+        assert!(!doc.is_dirty);
+        doc.redo();
+        assert!(doc.is_dirty);
+    }
+
+    #[test]
+    fn undo_after_deleting_the_file_stays_dirty() {
+        let mut doc = Document::new("abc".to_string(), None);
+        type_text(&mut doc, "x");
+        doc.mark_unsaved();
+        doc.undo();
+        assert!(doc.is_dirty);
+    }
+
+    #[test]
+    fn undo_restores_the_primary_cursor() {
+        let mut doc = Document::new("one two three".to_string(), None);
+        doc.set_cursors(vec![(0, 0), (4, 4), (8, 8)], 1);
+        type_text(&mut doc, "x");
+        doc.undo();
+        assert_eq!(doc.cursors, vec![(0, 0), (4, 4), (8, 8)]);
+        assert_eq!(doc.primary, 1);
+    }
+
+    #[test]
+    fn set_cursors_sorts_and_merges_overlaps() {
+        let mut doc = Document::new("0123456789".to_string(), None);
+        doc.set_cursors(vec![(6, 8), (0, 2), (1, 4), (5, 5), (5, 5)], 0);
+        assert_eq!(doc.cursors, vec![(0, 4), (5, 5), (6, 8)]);
+        // The merged selection stays primary
+        assert_eq!(doc.primary, 2);
+    }
+
+    #[test]
+    fn set_cursors_merges_a_caret_touching_a_selection_but_not_two_selections() {
+        let mut doc = Document::new("0123456789".to_string(), None);
+        doc.set_cursors(vec![(2, 4), (4, 6)], 0);
+        assert_eq!(doc.cursors, vec![(2, 4), (4, 6)]);
+        doc.set_cursors(vec![(2, 4), (4, 4)], 1);
+        assert_eq!(doc.cursors, vec![(2, 4)]);
+        assert_eq!(doc.primary, 0);
+    }
+
+    #[test]
+    fn set_cursors_keeps_the_direction_of_a_merged_selection() {
+        let mut doc = Document::new("0123456789".to_string(), None);
+        doc.set_cursors(vec![(5, 1), (3, 7)], 0);
+        assert_eq!(doc.cursors, vec![(7, 1)]);
+    }
+
+    #[test]
+    fn set_cursors_clamps_out_of_range_positions() {
+        let mut doc = Document::new("abc".to_string(), None);
+        doc.set_cursors(vec![(1, 99), (50, 60)], 5);
+        assert_eq!(doc.cursors, vec![(1, 3)]);
+        assert_eq!(doc.primary, 0);
+        let mut empty = Document::new(String::new(), None);
+        empty.set_cursors(vec![(3, 3)], 0);
+        assert_eq!(empty.cursors, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn typing_at_several_cursors_edits_each_line() {
+        let mut doc = Document::new("ab\nab\nab".to_string(), None);
+        doc.set_cursors(vec![(2, 2), (5, 5), (8, 8)], 2);
+        type_text(&mut doc, "x");
+        assert_eq!(doc.text(), "abx\nabx\nabx");
+        assert_eq!(doc.cursors, vec![(3, 3), (7, 7), (11, 11)]);
+        assert_eq!(doc.primary, 2);
+    }
+
+    #[test]
+    fn replacing_selections_shifts_the_cursors_after_them() {
+        let mut doc = Document::new("one two three".to_string(), None);
+        doc.set_cursors(vec![(0, 3), (13, 8)], 0);
+        type_text(&mut doc, "1");
+        assert_eq!(doc.text(), "1 two 1");
+        assert_eq!(doc.cursors, vec![(1, 1), (7, 7)]);
+    }
+
+    #[test]
+    fn edits_reaching_back_past_a_caret_merge_the_cursors() {
+        let mut doc = Document::new("abcdef".to_string(), None);
+        doc.set_cursors(vec![(2, 2), (3, 3)], 1);
+        doc.edit_cursors(|_, _, range| (range.start.saturating_sub(2)..range.end, String::new()));
+        assert_eq!(doc.text(), "def");
+        assert_eq!(doc.cursors, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn undoing_a_multi_cursor_edit_restores_every_cursor() {
+        let mut doc = Document::new("a b c".to_string(), None);
+        doc.set_cursors(vec![(0, 1), (2, 3), (4, 5)], 1);
+        type_text(&mut doc, "xy");
+        assert_eq!(doc.text(), "xy xy xy");
+        doc.undo();
+        assert_eq!(doc.text(), "a b c");
+        assert_eq!((doc.cursors.clone(), doc.primary), (vec![(0, 1), (2, 3), (4, 5)], 1));
+    }
+
+    #[test]
+    fn replace_text_edits_only_what_changed() {
+        for (old, new) in [("one two three", "one 2 three"), ("aaa", "aa"), ("aa", "aaaa"), ("", "x"), ("héllo", ""), ("ab", "ba")] {
+            let mut doc = Document::new(old.to_string(), None);
+            doc.replace_text(new);
+            assert_eq!(doc.text(), new);
+            doc.undo();
+            assert_eq!(doc.text(), old);
+        }
+    }
+
+    #[test]
+    fn replace_text_moves_cursors_along_with_the_text() {
+        let mut doc = Document::new("one two three".to_string(), None);
+        doc.set_cursors(vec![(0, 3), (5, 6), (8, 13)], 2);
+        doc.replace_text("one 2 three");
+        assert_eq!(doc.cursors, vec![(0, 3), (5, 5), (6, 11)]);
+        assert_eq!(doc.primary, 2);
+        assert!(doc.is_dirty);
+    }
+
+    #[test]
+    fn reload_with_the_same_text_is_not_an_undo_step() {
+        let mut doc = Document::new("same".to_string(), None);
+        doc.reload("same");
+        assert!(!doc.history.can_undo() && !doc.is_dirty);
+    }
+
+
+    #[test]
+    fn new_turns_crlf_into_lf() {
+        let mut doc = Document::new("a\r\nb\r\n".to_string(), None);
+        assert_eq!(doc.text(), "a\nb\n");
+        assert!(doc.line_ending == LineEnding::CrLf && !doc.mixed_line_endings);
     }
 }
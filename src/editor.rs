@@ -0,0 +1,688 @@
+use crate::document::Document;
+use crate::line_ending;
+use crate::syntax::SyntaxHighlighter;
+use egui::text::CCursor;
+use egui::{Color32, Event, EventFilter, FontId, Galley, ImeEvent, Key, Modifiers, Rect, Sense, Ui};
+use egui::{pos2, vec2};
+use std::ops::Range;
+use std::sync::Arc;
+
+// Gap between the left edge of the editor and the first column
+const TEXT_MARGIN: f32 = 4.0;
+
+/// Remembers the last block copied from a column selection, so pasting it at a single
+/// caret keeps its shape. Shared by all editors.
+#[derive(Clone)]
+struct BlockClipboard(String);
+
+/// Corners of a column selection, as (line, x) so the box keeps its width over short lines.
+#[derive(Clone, Copy)]
+struct ColumnSelection {
+    anchor: (usize, f32),
+    head: (usize, f32),
+}
+
+/// View state kept in egui memory, one per document.
+#[derive(Clone, Default)]
+struct EditorState {
+    // x position per cursor that Up/Down try to keep while moving through shorter lines
+    preferred_x: Vec<f32>,
+    // IME composition that has not been committed yet
+    preedit: String,
+    // Widest line laid out so far, sizes the horizontal scroll range
+    content_width: f32,
+    // Set while Alt+Shift+drag or Alt+Shift+arrows is growing a column selection
+    column: Option<ColumnSelection>,
+}
+
+pub struct EditorOutput {
+    pub visible_lines: Range<usize>,
+    pub row_height: f32,
+}
+
+/// Editor widget that renders and edits a `Document` straight from its rope,
+/// laying out only the lines inside the viewport.
+pub struct EditorView<'a> {
+    doc: &'a mut Document,
+    highlighter: &'a SyntaxHighlighter,
+    theme: &'a str,
+    font_id: FontId,
+    // Sorted char ranges painted as search hits
+    search_matches: &'a [Range<usize>],
+}
+
+impl<'a> EditorView<'a> {
+    pub fn new(doc: &'a mut Document, highlighter: &'a SyntaxHighlighter, theme: &'a str, font_id: FontId) -> Self {
+        Self { doc, highlighter, theme, font_id, search_matches: &[] }
+    }
+
+    /// Highlights these (sorted, non-overlapping) char ranges behind the text.
+    pub fn search_matches(mut self, matches: &'a [Range<usize>]) -> Self {
+        self.search_matches = matches;
+        self
+    }
+
+    /// The widget id of the editor showing `doc`, e.g. to check whether it has focus.
+    pub fn id_for(doc: &Document) -> egui::Id {
+        egui::Id::new("editor").with(doc.id)
+    }
+
+    pub fn show(mut self, ui: &mut Ui) -> EditorOutput {
+        let id = Self::id_for(self.doc);
+        let mut state: EditorState = ui.data_mut(|d| d.get_temp(id)).unwrap_or_default();
+        let row_height = ui.fonts(|f| f.row_height(&self.font_id));
+        let cursors_before = self.doc.cursor_state();
+        let revision = self.doc.revision();
+
+        let output = egui::ScrollArea::both()
+            .id_salt(id)
+            .auto_shrink([false; 2])
+            .scroll_offset(self.doc.scroll_offset)
+            .show_viewport(ui, |ui, viewport| {
+                let size = vec2(
+                    state.content_width.max(ui.available_width()),
+                    self.doc.rope.len_lines() as f32 * row_height,
+                );
+                let (_, rect) = ui.allocate_space(size);
+                let response = ui.interact(rect, id, Sense::click_and_drag());
+                let origin = rect.min + vec2(TEXT_MARGIN, 0.0);
+
+                if response.is_pointer_button_down_on() || ui.memory(|m| m.focused().is_none()) {
+                    response.request_focus();
+                }
+                let rows_per_page = (viewport.height() / row_height).floor().max(1.0) as usize;
+                let mut reveal = self.handle_pointer(ui, &mut state, &response, origin, row_height);
+                if response.has_focus() {
+                    ui.memory_mut(|m| {
+                        m.set_focus_lock_filter(id, EventFilter {
+                            tab: true,
+                            horizontal_arrows: true,
+                            vertical_arrows: true,
+                            // Escape collapses multiple cursors instead of leaving the editor
+                            escape: self.doc.cursors.len() > 1,
+                        })
+                    });
+                    reveal |= self.handle_keyboard(ui, &mut state, rows_per_page);
+                }
+                // Matches found before this frame's edits can point past the new text
+                if self.doc.revision() != revision {
+                    self.search_matches = &[];
+                }
+
+                let total_lines = self.doc.rope.len_lines();
+                let first = ((viewport.min.y / row_height).floor().max(0.0) as usize).min(total_lines);
+                let last = ((viewport.max.y / row_height).ceil().max(0.0) as usize + 1).min(total_lines);
+                let visible_lines = first..last;
+
+                let galleys = self.layout_lines(ui, visible_lines.clone());
+                for (_, galley) in &galleys {
+                    state.content_width = state.content_width.max(galley.size().x + 2.0 * TEXT_MARGIN + row_height);
+                }
+                let cursor_rect = self.paint(ui, &response, &state, origin, row_height, &galleys);
+
+                if reveal || self.doc.reveal_cursor {
+                    self.doc.reveal_cursor = false;
+                    ui.scroll_to_rect(cursor_rect.expand2(vec2(row_height, row_height)), None);
+                }
+                if response.has_focus() {
+                    let to_global = ui.ctx().layer_transform_to_global(ui.layer_id()).unwrap_or_default();
+                    ui.ctx().output_mut(|o| {
+                        o.ime = Some(egui::output::IMEOutput {
+                            rect: to_global * ui.clip_rect(),
+                            cursor_rect: to_global * cursor_rect,
+                        });
+                    });
+                }
+                visible_lines
+            });
+
+        self.doc.scroll_offset = output.state.offset;
+        self.doc.commit_edits(cursors_before);
+        ui.data_mut(|d| d.insert_temp(id, state));
+
+        EditorOutput {
+            visible_lines: output.inner,
+            row_height,
+        }
+    }
+
+    fn layout_lines(&self, ui: &Ui, lines: Range<usize>) -> Vec<(usize, Arc<Galley>)> {
+        let doc = &*self.doc;
+        let jobs = self.highlighter.highlight_lines(&doc.highlight, &doc.rope, lines.clone(), &doc.language, self.theme, &self.font_id);
+        ui.fonts(|f| lines.zip(jobs).map(|(line, job)| (line, f.layout_job(job))).collect())
+    }
+
+    // Unstyled layout of a single line; same geometry as the highlighted one, used for hit-testing
+    fn line_galley(&self, ui: &Ui, line: usize) -> Arc<Galley> {
+        let text = self.doc.line_text(line);
+        ui.fonts(|f| f.layout_no_wrap(text, self.font_id.clone(), Color32::PLACEHOLDER))
+    }
+
+    fn char_at_pos(&self, ui: &Ui, origin: egui::Pos2, row_height: f32, pos: egui::Pos2) -> usize {
+        let last_line = self.doc.rope.len_lines().saturating_sub(1);
+        let line = (((pos.y - origin.y) / row_height).floor().max(0.0) as usize).min(last_line);
+        let galley = self.line_galley(ui, line);
+        let column = galley.cursor_from_pos(vec2(pos.x - origin.x, row_height / 2.0)).ccursor.index;
+        self.doc.rope.line_to_char(line) + column.min(self.doc.line_len(line))
+    }
+
+    fn x_of(&self, ui: &Ui, index: usize) -> f32 {
+        let line = self.doc.rope.char_to_line(index);
+        let column = index - self.doc.rope.line_to_char(line);
+        self.line_galley(ui, line).pos_from_ccursor(CCursor::new(column)).min.x
+    }
+
+    fn handle_pointer(&mut self, ui: &Ui, state: &mut EditorState, response: &egui::Response, origin: egui::Pos2, row_height: f32) -> bool {
+        let Some(pos) = response.interact_pointer_pos() else {
+            return false;
+        };
+        let index = self.char_at_pos(ui, origin, row_height, pos);
+        let pressed = response.is_pointer_button_down_on() && ui.input(|i| i.pointer.any_pressed());
+
+        let modifiers = ui.input(|i| i.modifiers);
+        let mut cursors = self.doc.cursors.clone();
+        let primary = self.doc.primary;
+
+        let last_line = self.doc.rope.len_lines().saturating_sub(1);
+        let point = (
+            (((pos.y - origin.y) / row_height).floor().max(0.0) as usize).min(last_line),
+            (pos.x - origin.x).max(0.0),
+        );
+        if pressed {
+            state.column = (modifiers.alt && modifiers.shift).then_some(ColumnSelection { anchor: point, head: point });
+        }
+        if let Some(column) = &mut state.column {
+            if !pressed && !response.dragged() {
+                return false;
+            }
+            column.head = point;
+            let column = *column;
+            self.select_column(ui, column);
+            return true;
+        }
+
+        if response.double_clicked() {
+            let range = word_range_at(self.doc, index);
+            cursors[primary] = (range.start, range.end);
+        } else if pressed && modifiers.alt && !modifiers.shift {
+            self.doc.add_cursor((index, index));
+            return true;
+        } else if pressed && modifiers.shift {
+            cursors[primary].1 = index;
+        } else if pressed {
+            cursors = vec![(index, index)];
+        } else if response.dragged() {
+            cursors[primary].1 = index;
+        } else {
+            return false;
+        }
+        let primary = primary.min(cursors.len() - 1);
+        self.doc.set_cursors(cursors, primary);
+        true
+    }
+
+    fn handle_keyboard(&mut self, ui: &Ui, state: &mut EditorState, rows_per_page: usize) -> bool {
+        let events = ui.input(|i| i.events.clone());
+        let mut acted = false;
+
+        for event in events {
+            // Anything but Alt+Shift+arrows ends the column selection
+            let ends_column = match &event {
+                Event::Key { key, pressed: true, modifiers, .. } => {
+                    !(modifiers.alt && modifiers.shift && matches!(key, Key::ArrowUp | Key::ArrowDown | Key::ArrowLeft | Key::ArrowRight))
+                }
+                Event::Text(_) | Event::Paste(_) | Event::Cut | Event::Ime(ImeEvent::Commit(_)) => true,
+                _ => false,
+            };
+            if ends_column {
+                state.column = None;
+            }
+
+            match event {
+                Event::Text(text) if state.preedit.is_empty() && text != "\n" && text != "\r" => {
+                    self.doc.replace_selection(&line_ending::normalize(&text));
+                    acted = true;
+                }
+                Event::Paste(text) => {
+                    // The rope only ever holds `\n` line breaks
+                    let text = line_ending::normalize(&text);
+                    // A block with one line per cursor is spread across the cursors
+                    let lines: Vec<&str> = text.lines().collect();
+                    let block = ui.data(|d| d.get_temp::<BlockClipboard>(egui::Id::new("block_clipboard"))).is_some_and(|b| b.0 == text);
+                    if self.doc.cursors.len() > 1 && lines.len() == self.doc.cursors.len() {
+                        self.doc.edit_cursors(|_, i, range| (range, lines[i].to_string()));
+                    } else if block && self.doc.cursors.len() == 1 {
+                        self.paste_block(ui, &lines);
+                    } else {
+                        self.doc.replace_selection(&text);
+                    }
+                    acted = true;
+                }
+                Event::Copy | Event::Cut if self.doc.cursors.iter().any(|(anchor, head)| anchor != head) => {
+                    let text = self.doc.selected_text();
+                    if self.doc.cursors.len() > 1 {
+                        ui.data_mut(|d| d.insert_temp(egui::Id::new("block_clipboard"), BlockClipboard(text.clone())));
+                    }
+                    ui.ctx().copy_text(text);
+                    if matches!(event, Event::Cut) {
+                        self.doc.replace_selection("");
+                        acted = true;
+                    }
+                }
+                Event::Ime(ime) => match ime {
+                    ImeEvent::Preedit(text) => state.preedit = text,
+                    ImeEvent::Commit(text) => {
+                        state.preedit.clear();
+                        if !text.is_empty() {
+                            self.doc.replace_selection(&line_ending::normalize(&text));
+                            acted = true;
+                        }
+                    }
+                    ImeEvent::Enabled | ImeEvent::Disabled => state.preedit.clear(),
+                },
+                Event::Key { key, pressed: true, modifiers, .. } if state.preedit.is_empty() => {
+                    let vertical = matches!(key, Key::ArrowUp | Key::ArrowDown | Key::PageUp | Key::PageDown);
+                    if !vertical {
+                        state.preferred_x.clear();
+                    }
+                    acted |= self.handle_key(ui, state, key, modifiers, rows_per_page);
+                }
+                _ => {}
+            }
+        }
+        acted
+    }
+
+    fn handle_key(&mut self, ui: &Ui, state: &mut EditorState, key: Key, modifiers: Modifiers, rows_per_page: usize) -> bool {
+        if modifiers.alt && modifiers.shift && matches!(key, Key::ArrowUp | Key::ArrowDown | Key::ArrowLeft | Key::ArrowRight) {
+            self.extend_column(ui, state, key);
+            return true;
+        }
+
+        let doc = &mut *self.doc;
+        let word = modifiers.command || modifiers.alt;
+
+        match key {
+            Key::A if modifiers.command => {
+                doc.set_selection((0, doc.rope.len_chars()));
+                return true;
+            }
+            Key::D if modifiers.command => {
+                if doc.selected_range().is_empty() {
+                    select_words(doc);
+                } else {
+                    doc.add_next_occurrence();
+                }
+                return true;
+            }
+            Key::L if modifiers.command && modifiers.shift => {
+                if doc.selected_range().is_empty() {
+                    select_words(doc);
+                }
+                doc.select_all_occurrences();
+                return true;
+            }
+            Key::Escape if doc.cursors.len() > 1 => {
+                doc.set_selection(doc.selection());
+                return true;
+            }
+            Key::Enter => {
+                doc.edit_cursors(|doc, _, range| {
+                    let indent = line_indent(doc, range.start);
+                    (range, format!("\n{indent}"))
+                });
+                return true;
+            }
+            Key::Tab if !modifiers.shift => {
+                doc.replace_selection("\t");
+                return true;
+            }
+            Key::Backspace | Key::Delete => {
+                doc.edit_cursors(|doc, _, range| {
+                    let head = range.start;
+                    let range = match (range.is_empty(), key, word) {
+                        (false, ..) => range,
+                        (true, Key::Backspace, true) => word_left(doc, head)..head,
+                        (true, Key::Backspace, false) => move_left(doc, head)..head,
+                        (true, _, true) => head..word_right(doc, head),
+                        (true, _, false) => head..move_right(doc, head),
+                    };
+                    (range, String::new())
+                });
+                return true;
+            }
+            _ => {}
+        }
+
+        let vertical = matches!(key, Key::ArrowUp | Key::ArrowDown | Key::PageUp | Key::PageDown);
+        if vertical && state.preferred_x.len() != self.doc.cursors.len() {
+            state.preferred_x = self.doc.cursors.iter().map(|&(_, head)| self.x_of(ui, head)).collect();
+        }
+
+        let mut cursors = Vec::with_capacity(self.doc.cursors.len());
+        for (i, &(anchor, head)) in self.doc.cursors.iter().enumerate() {
+            let doc = &*self.doc;
+            let range = anchor.min(head)..anchor.max(head);
+            let target = match key {
+                Key::ArrowLeft if !modifiers.shift && !range.is_empty() => range.start,
+                Key::ArrowRight if !modifiers.shift && !range.is_empty() => range.end,
+                Key::ArrowLeft if word => word_left(doc, head),
+                Key::ArrowLeft => move_left(doc, head),
+                Key::ArrowRight if word => word_right(doc, head),
+                Key::ArrowRight => move_right(doc, head),
+                Key::Home if modifiers.command => 0,
+                Key::Home => smart_home(doc, head),
+                Key::End if modifiers.command => doc.rope.len_chars(),
+                Key::End => {
+                    let line = doc.rope.char_to_line(head);
+                    doc.rope.line_to_char(line) + doc.line_len(line)
+                }
+                Key::ArrowUp | Key::ArrowDown | Key::PageUp | Key::PageDown => {
+                    let rows = if matches!(key, Key::PageUp | Key::PageDown) { rows_per_page } else { 1 };
+                    let up = matches!(key, Key::ArrowUp | Key::PageUp);
+                    let line = doc.rope.char_to_line(head);
+                    let last_line = doc.rope.len_lines().saturating_sub(1);
+                    let target_line = if up { line.saturating_sub(rows) } else { (line + rows).min(last_line) };
+                    let x = state.preferred_x[i];
+                    let column = self.line_galley(ui, target_line).cursor_from_pos(vec2(x, 0.0)).ccursor.index;
+                    doc.rope.line_to_char(target_line) + column
+                }
+                _ => return false,
+            };
+            cursors.push((if modifiers.shift { anchor } else { target }, target));
+        }
+
+        let primary = self.doc.primary;
+        self.doc.set_cursors(cursors, primary);
+        // Cursors that ran into each other were merged; keep the remembered columns in step
+        if state.preferred_x.len() != self.doc.cursors.len() {
+            state.preferred_x.clear();
+        }
+        true
+    }
+
+    // Alt+Shift+arrows grow the column selection from the primary caret by a line or a character
+    fn extend_column(&mut self, ui: &Ui, state: &mut EditorState, key: Key) {
+        let mut column = state.column.unwrap_or_else(|| {
+            let head = self.doc.selection().1;
+            let point = (self.doc.rope.char_to_line(head), self.x_of(ui, head));
+            ColumnSelection { anchor: point, head: point }
+        });
+        let char_width = ui.fonts(|f| f.glyph_width(&self.font_id, ' '));
+        let last_line = self.doc.rope.len_lines().saturating_sub(1);
+        let (line, x) = &mut column.head;
+        match key {
+            Key::ArrowUp => *line = line.saturating_sub(1),
+            Key::ArrowDown => *line = (*line + 1).min(last_line),
+            Key::ArrowLeft => *x = (*x - char_width).max(0.0),
+            _ => *x += char_width,
+        }
+        state.column = Some(column);
+        self.select_column(ui, column);
+    }
+
+    // One cursor per line of the box, clamped to the end of lines that are too short
+    fn select_column(&mut self, ui: &Ui, column: ColumnSelection) {
+        let (anchor_line, anchor_x) = column.anchor;
+        let (head_line, head_x) = column.head;
+        let lines = anchor_line.min(head_line)..=anchor_line.max(head_line);
+        let cursors = lines.clone()
+            .map(|line| {
+                let galley = self.line_galley(ui, line);
+                let start = self.doc.rope.line_to_char(line);
+                let at = |x: f32| start + galley.cursor_from_pos(vec2(x, 0.0)).ccursor.index;
+                (at(anchor_x), at(head_x))
+            })
+            .collect();
+        let primary = if head_line < anchor_line { 0 } else { lines.count() - 1 };
+        self.doc.set_cursors(cursors, primary);
+    }
+
+    // Pastes a copied block at the caret's column on consecutive lines, padding short lines
+    fn paste_block(&mut self, ui: &Ui, lines: &[&str]) {
+        let (anchor, head) = self.doc.selection();
+        self.doc.delete(anchor.min(head)..anchor.max(head));
+        let caret = anchor.min(head);
+        let first_line = self.doc.rope.char_to_line(caret);
+        let x = self.x_of(ui, caret);
+
+        let missing = (first_line + lines.len()).saturating_sub(self.doc.rope.len_lines());
+        self.doc.insert(self.doc.rope.len_chars(), &"\n".repeat(missing));
+
+        let char_width = ui.fonts(|f| f.glyph_width(&self.font_id, ' '));
+        let mut padding = Vec::with_capacity(lines.len());
+        let mut cursors = Vec::with_capacity(lines.len());
+        for line in first_line..first_line + lines.len() {
+            let galley = self.line_galley(ui, line);
+            let column = galley.cursor_from_pos(vec2(x, 0.0)).ccursor.index;
+            let short_by = x - galley.size().x;
+            padding.push(if short_by > 0.0 { " ".repeat((short_by / char_width).round() as usize) } else { String::new() });
+            let at = self.doc.rope.line_to_char(line) + column;
+            cursors.push((at, at));
+        }
+        self.doc.set_cursors(cursors, 0);
+        self.doc.edit_cursors(|_, i, range| (range, format!("{}{}", padding[i], lines[i])));
+    }
+
+    fn paint(
+        &self,
+        ui: &Ui,
+        response: &egui::Response,
+        state: &EditorState,
+        origin: egui::Pos2,
+        row_height: f32,
+        galleys: &[(usize, Arc<Galley>)],
+    ) -> Rect {
+        let painter = ui.painter();
+        let visuals = ui.visuals();
+        let rope = &self.doc.rope;
+        let primary_head = self.doc.selection().1;
+        let head_line = rope.char_to_line(primary_head);
+        let head_column = primary_head - rope.line_to_char(head_line);
+
+        // Only the cursors touching the visible lines are painted
+        let cursors = match (galleys.first(), galleys.last()) {
+            (Some((first, _)), Some((last, _))) => {
+                let visible = rope.line_to_char(*first)..rope.line_to_char(*last) + self.doc.line_len(*last);
+                let from = self.doc.cursors.partition_point(|&(anchor, head)| anchor.max(head) < visible.start);
+                let to = self.doc.cursors.partition_point(|&(anchor, head)| anchor.min(head) <= visible.end);
+                &self.doc.cursors[from..to.max(from)]
+            }
+            _ => &[][..],
+        };
+
+        let match_color = visuals.warn_fg_color.gamma_multiply(0.35);
+        let mut cursor_rect = None;
+        for (line, galley) in galleys {
+            let line = *line;
+            let top_left = pos2(origin.x, origin.y + line as f32 * row_height);
+            let line_start = rope.line_to_char(line);
+            let line_len = self.doc.line_len(line);
+            let line_end = line_start + line_len;
+            let span_rect = |from: usize, to: usize| {
+                let x0 = galley.pos_from_ccursor(CCursor::new(from - line_start)).min.x;
+                let x1 = galley.pos_from_ccursor(CCursor::new(to - line_start)).min.x;
+                Rect::from_min_max(top_left + vec2(x0, 0.0), top_left + vec2(x1, row_height))
+            };
+
+            let first_match = self.search_matches.partition_point(|m| m.end <= line_start);
+            for found in self.search_matches[first_match..].iter().take_while(|m| m.start <= line_end) {
+                let rect = span_rect(found.start.max(line_start), found.end.min(line_end));
+                painter.rect_filled(rect, 2.0, match_color);
+            }
+
+            // Selection highlight, extended a little past the end of lines whose break is selected
+            for &(anchor, head) in cursors {
+                let selection = anchor.min(head)..anchor.max(head);
+                if !selection.is_empty() && selection.start <= line_end && selection.end > line_start {
+                    let mut rect = span_rect(selection.start.max(line_start), selection.end.min(line_end));
+                    if selection.end > line_end {
+                        rect.max.x += row_height * 0.4;
+                    }
+                    painter.rect_filled(rect, 0.0, visuals.selection.bg_fill);
+                }
+            }
+
+            painter.galley(top_left, galley.clone(), visuals.text_color());
+
+            // Secondary carets; the primary one is painted below, after the IME preedit
+            if response.has_focus() {
+                for &(_, head) in cursors {
+                    if (line_start..=line_end).contains(&head) && head != primary_head {
+                        let x = galley.pos_from_ccursor(CCursor::new(head - line_start)).min.x;
+                        let rect = Rect::from_min_size(top_left + vec2(x, 0.0), vec2(2.0, row_height));
+                        painter.rect_filled(rect, 0.0, visuals.text_cursor.stroke.color);
+                    }
+                }
+            }
+            if line == head_line {
+                let x = galley.pos_from_ccursor(CCursor::new(head_column)).min.x;
+                cursor_rect = Some(Rect::from_min_size(top_left + vec2(x, 0.0), vec2(2.0, row_height)));
+            }
+        }
+
+        // The caret may be off-screen; still report where it is so the view can scroll to it
+        let mut cursor_rect = cursor_rect.unwrap_or_else(|| {
+            let x = self.x_of(ui, primary_head);
+            Rect::from_min_size(pos2(origin.x + x, origin.y + head_line as f32 * row_height), vec2(2.0, row_height))
+        });
+
+        if response.has_focus() {
+            if !state.preedit.is_empty() {
+                let galley = ui.fonts(|f| f.layout_no_wrap(state.preedit.clone(), self.font_id.clone(), visuals.strong_text_color()));
+                let rect = Rect::from_min_size(cursor_rect.min, vec2(galley.size().x, row_height));
+                painter.rect_filled(rect, 0.0, visuals.extreme_bg_color);
+                painter.galley(rect.min, galley, visuals.strong_text_color());
+                painter.hline(rect.x_range(), rect.bottom() - 1.0, visuals.text_cursor.stroke);
+                cursor_rect = cursor_rect.translate(vec2(rect.width(), 0.0));
+            }
+            painter.rect_filled(cursor_rect, 0.0, visuals.text_cursor.stroke.color);
+        }
+        cursor_rect
+    }
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+    Word,
+    Space,
+    Punct,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else if c.is_whitespace() {
+        CharClass::Space
+    } else {
+        CharClass::Punct
+    }
+}
+
+fn move_left(doc: &Document, index: usize) -> usize {
+    let line = doc.rope.char_to_line(index);
+    let line_start = doc.rope.line_to_char(line);
+    if index > line_start {
+        index - 1
+    } else if line > 0 {
+        doc.rope.line_to_char(line - 1) + doc.line_len(line - 1)
+    } else {
+        index
+    }
+}
+
+fn move_right(doc: &Document, index: usize) -> usize {
+    let line = doc.rope.char_to_line(index);
+    let line_end = doc.rope.line_to_char(line) + doc.line_len(line);
+    if index < line_end {
+        index + 1
+    } else if line + 1 < doc.rope.len_lines() {
+        doc.rope.line_to_char(line + 1)
+    } else {
+        index
+    }
+}
+
+fn word_left(doc: &Document, index: usize) -> usize {
+    let line_start = doc.rope.line_to_char(doc.rope.char_to_line(index));
+    if index == line_start {
+        return move_left(doc, index);
+    }
+    let mut i = index;
+    while i > line_start && char_class(doc.rope.char(i - 1)) == CharClass::Space {
+        i -= 1;
+    }
+    if i > line_start {
+        let class = char_class(doc.rope.char(i - 1));
+        while i > line_start && char_class(doc.rope.char(i - 1)) == class {
+            i -= 1;
+        }
+    }
+    i
+}
+
+fn word_right(doc: &Document, index: usize) -> usize {
+    let line = doc.rope.char_to_line(index);
+    let line_end = doc.rope.line_to_char(line) + doc.line_len(line);
+    if index == line_end {
+        return move_right(doc, index);
+    }
+    let mut i = index;
+    let class = char_class(doc.rope.char(i));
+    while i < line_end && char_class(doc.rope.char(i)) == class {
+        i += 1;
+    }
+    while i < line_end && char_class(doc.rope.char(i)) == CharClass::Space {
+        i += 1;
+    }
+    i
+}
+
+pub fn word_range_at(doc: &Document, index: usize) -> Range<usize> {
+    let line = doc.rope.char_to_line(index);
+    let line_start = doc.rope.line_to_char(line);
+    let line_end = line_start + doc.line_len(line);
+    if line_start == line_end {
+        return index..index;
+    }
+    let probe = index.min(line_end - 1);
+    let class = char_class(doc.rope.char(probe));
+    let mut start = probe;
+    while start > line_start && char_class(doc.rope.char(start - 1)) == class {
+        start -= 1;
+    }
+    let mut end = probe;
+    while end < line_end && char_class(doc.rope.char(end)) == class {
+        end += 1;
+    }
+    start..end
+}
+
+// Ctrl+D / Ctrl+Shift+L on bare carets first select the word under each of them
+fn select_words(doc: &mut Document) {
+    let words = doc.cursors.iter()
+        .map(|&(_, head)| {
+            let range = word_range_at(doc, head);
+            (range.start, range.end)
+        })
+        .collect();
+    let primary = doc.primary;
+    doc.set_cursors(words, primary);
+}
+
+// Home jumps to the first non-blank column, or to column 0 if already there
+fn smart_home(doc: &Document, index: usize) -> usize {
+    let line = doc.rope.char_to_line(index);
+    let line_start = doc.rope.line_to_char(line);
+    let indent = line_indent(doc, line_start + doc.line_len(line)).chars().count();
+    if index == line_start + indent { line_start } else { line_start + indent }
+}
+
+// Leading whitespace of the line containing `index`, up to `index`
+fn line_indent(doc: &Document, index: usize) -> String {
+    let line_start = doc.rope.line_to_char(doc.rope.char_to_line(index));
+    doc.rope.slice(line_start..index)
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect()
+}
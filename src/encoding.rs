@@ -0,0 +1,234 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Offered by Reopen/Save with Encoding, besides the UTF-8 and UTF-16 variants.
+const LEGACY: &[&Encoding] = &[
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::WINDOWS_1250,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::GBK,
+    encoding_rs::GB18030,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+];
+
+// How much of a file is looked at to tell text from binary and to spot BOM-less UTF-16
+const SNIFF_LEN: usize = 8192;
+
+/// The encoding a document was read in and is saved back in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    // Whether the file starts with a byte order mark
+    pub bom: bool,
+}
+
+impl TextEncoding {
+    pub const UTF8: Self = Self { encoding: UTF_8, bom: false };
+
+    /// Every encoding the app offers to reopen or save with.
+    pub fn all() -> Vec<Self> {
+        let unicode = [
+            Self::UTF8,
+            Self { encoding: UTF_8, bom: true },
+            Self { encoding: UTF_16LE, bom: true },
+            Self { encoding: UTF_16BE, bom: true },
+        ];
+        unicode.into_iter().chain(LEGACY.iter().map(|&encoding| Self { encoding, bom: false })).collect()
+    }
+
+    /// Guesses the encoding of a file's contents: a byte order mark wins, then UTF-16 without
+    /// a BOM, then valid UTF-8, and otherwise whatever legacy encoding the text looks like.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+            return Self { encoding, bom: true };
+        }
+        // Before UTF-8, which ASCII-only UTF-16 is valid as too
+        if let Some(encoding) = utf16_without_bom(&bytes[..bytes.len().min(SNIFF_LEN)]) {
+            return Self { encoding, bom: false };
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            return Self::UTF8;
+        }
+        let mut detector = chardetng::EncodingDetector::new();
+        detector.feed(bytes, true);
+        Self { encoding: detector.guess(None, true), bom: false }
+    }
+
+    /// Name shown in the status bar, also used to remember the encoding in the session.
+    pub fn label(self) -> String {
+        if self.bom {
+            format!("{} with BOM", self.encoding.name())
+        } else {
+            self.encoding.name().to_string()
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        let (name, bom) = match label.strip_suffix(" with BOM") {
+            Some(name) => (name, true),
+            None => (label, false),
+        };
+        Encoding::for_label(name.as_bytes()).map(|encoding| Self { encoding, bom })
+    }
+
+    fn is_utf16(self) -> bool {
+        self.encoding == UTF_16LE || self.encoding == UTF_16BE
+    }
+
+    /// Decodes `bytes`, dropping a BOM that matches. Also returns whether some bytes weren't
+    /// valid in this encoding and were replaced with U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> (String, bool) {
+        let (text, had_errors) = self.encoding.decode_with_bom_removal(bytes);
+        (text.into_owned(), had_errors)
+    }
+
+    /// Encodes `text`, with a BOM if the file had one. Fails on characters the encoding can't
+    /// represent rather than writing substitutes for them.
+    pub fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+        // encoding_rs only decodes UTF-16, so it is written by hand
+        if self.is_utf16() {
+            let little_endian = self.encoding == UTF_16LE;
+            let bom = self.bom.then_some(0xfeff);
+            let bytes = bom
+                .into_iter()
+                .chain(text.encode_utf16())
+                .flat_map(|unit: u16| if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() })
+                .collect();
+            return Ok(bytes);
+        }
+        let mut bytes = if self.bom { "\u{feff}".as_bytes().to_vec() } else { Vec::new() };
+        if self.encoding == UTF_8 {
+            bytes.extend_from_slice(text.as_bytes());
+            return Ok(bytes);
+        }
+        let mut encoder = self.encoding.new_encoder();
+        let mut buffer = vec![0; encoder.max_buffer_length_from_utf8_without_replacement(text.len()).unwrap_or(text.len() * 4)];
+        let (result, read, written) = encoder.encode_from_utf8_without_replacement(text, &mut buffer, true);
+        match result {
+            encoding_rs::EncoderResult::InputEmpty => {
+                bytes.extend_from_slice(&buffer[..written]);
+                Ok(bytes)
+            }
+            encoding_rs::EncoderResult::Unmappable(c) => {
+                let line = text[..read].lines().count().max(1);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{c:?} on line {line} can't be written as {}", self.encoding.name()),
+                ))
+            }
+            encoding_rs::EncoderResult::OutputFull => Err(io::Error::other("the encoder ran out of space")),
+        }
+    }
+}
+
+/// A file's text and how it was decoded.
+pub struct Decoded {
+    pub text: String,
+    pub encoding: TextEncoding,
+    // Some bytes weren't valid in the encoding and were replaced with U+FFFD
+    pub had_errors: bool,
+}
+
+/// Reads a text file in whatever encoding it turns out to be in.
+pub fn read(path: &Path) -> io::Result<Decoded> {
+    let bytes = fs::read(path)?;
+    let encoding = TextEncoding::detect(&bytes);
+    // NUL bytes are common in UTF-16 but practically never in other text
+    if !encoding.is_utf16() && bytes[..bytes.len().min(SNIFF_LEN)].contains(&0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "this looks like a binary file"));
+    }
+    let (text, had_errors) = encoding.decode(&bytes);
+    Ok(Decoded { text, encoding, had_errors })
+}
+
+/// Reads a file in an encoding that is already known, e.g. to reload an open document.
+pub fn read_as(path: &Path, encoding: TextEncoding) -> io::Result<Decoded> {
+    let (text, had_errors) = encoding.decode(&fs::read(path)?);
+    Ok(Decoded { text, encoding, had_errors })
+}
+
+// ASCII-heavy UTF-16 has a zero in every other byte: the high byte comes first in big endian
+fn utf16_without_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    let pairs = bytes.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let zeros_at = |parity: usize| bytes.iter().skip(parity).step_by(2).take(pairs).filter(|&&b| b == 0).count();
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    let mostly = |count: usize| count * 10 >= pairs * 4;
+    let rarely = |count: usize| count * 20 <= pairs;
+    if mostly(even) && rarely(odd) {
+        Some(UTF_16BE)
+    } else if mostly(odd) && rarely(even) {
+        Some(UTF_16LE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, little_endian: bool) -> Vec<u8> {
+        text.encode_utf16().flat_map(|unit| if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() }).collect()
+    }
+
+    #[test]
+    fn utf16_without_bom_tells_the_byte_order() {
+        assert_eq!(utf16_without_bom(&utf16("hello", true)), Some(UTF_16LE));
+        assert_eq!(utf16_without_bom(&utf16("hello", false)), Some(UTF_16BE));
+        assert_eq!(utf16_without_bom(b"hello"), None);
+    }
+
+    #[test]
+    fn utf16_without_bom_needs_a_few_units() {
+        assert_eq!(utf16_without_bom(b""), None);
+        assert_eq!(utf16_without_bom(&utf16("a", true)), None);
+        assert_eq!(utf16_without_bom(&[0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn detect_prefers_a_bom() {
+        assert_eq!(TextEncoding::detect(b"\xef\xbb\xbfabc"), TextEncoding { encoding: UTF_8, bom: true });
+        assert_eq!(TextEncoding::detect(b"\xff\xfea\0b\0"), TextEncoding { encoding: UTF_16LE, bom: true });
+        assert_eq!(TextEncoding::detect(b""), TextEncoding::UTF8);
+        assert_eq!(TextEncoding::detect("héllo".as_bytes()), TextEncoding::UTF8);
+    }
+
+    #[test]
+    fn decode_drops_the_bom_and_reports_invalid_bytes() {
+        let with_bom = TextEncoding { encoding: UTF_8, bom: true };
+        assert_eq!(with_bom.decode(b"\xef\xbb\xbfa\r\nb"), ("a\r\nb".to_string(), false));
+        assert_eq!(TextEncoding::UTF8.decode(b"a\xffb"), ("a\u{fffd}b".to_string(), true));
+    }
+
+    #[test]
+    fn encode_round_trips_every_offered_encoding() {
+        for encoding in TextEncoding::all() {
+            let text = "line one\r\nline two\n";
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(TextEncoding::detect(&bytes).bom, encoding.bom, "{}", encoding.label());
+            assert_eq!(encoding.decode(&bytes), (text.to_string(), false), "{}", encoding.label());
+        }
+        assert_eq!(TextEncoding { encoding: UTF_16BE, bom: true }.encode("a").unwrap(), vec![0xfe, 0xff, 0, b'a']);
+        assert_eq!(TextEncoding { encoding: UTF_16LE, bom: false }.encode("").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn encode_fails_on_unmappable_characters() {
+        let latin1 = TextEncoding { encoding: encoding_rs::WINDOWS_1252, bom: false };
+        assert_eq!(latin1.encode("café").unwrap(), b"caf\xe9");
+        let error = latin1.encode("ok\nstill ok\n→").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 3"), "{error}");
+    }
+}
@@ -0,0 +1,594 @@
+use crate::settings::Settings;
+use crate::walk::{IgnoreRules, Walk, WalkEntry};
+use egui::Ui;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What happened in the tree that the rest of the app has to follow.
+pub enum TreeEvent {
+    Open(PathBuf),
+    Renamed { from: PathBuf, to: PathBuf },
+    Deleted(PathBuf),
+    // Remove Folder from Workspace was picked on the root
+    RemoveRoot(PathBuf),
+    // A folder was expanded or collapsed, so the settings need saving
+    ExpandedChanged,
+}
+
+// Context menu commands, run after the tree is drawn
+enum Command {
+    NewFile(PathBuf),
+    NewFolder(PathBuf),
+    Rename(PathBuf),
+    Duplicate(PathBuf),
+    Delete(PathBuf),
+}
+
+// An entry being named in place
+struct Naming {
+    target: NameTarget,
+    name: String,
+    focus: bool,
+}
+
+#[derive(Clone, PartialEq)]
+enum NameTarget {
+    // New entries go in this directory
+    NewFile(PathBuf),
+    NewFolder(PathBuf),
+    Rename(PathBuf),
+}
+
+/// Lazily loaded directory tree for the sidebar.
+pub struct FileTree {
+    root: PathBuf,
+    // Listings of the directories read so far, folders first
+    children: HashMap<PathBuf, Vec<WalkEntry>>,
+    expanded: HashSet<PathBuf>,
+    // Scroll to the active file on the next frame
+    reveal: bool,
+    // Last active document path and its tree spelling, to avoid resolving it every frame
+    active: Option<(PathBuf, Option<PathBuf>)>,
+    naming: Option<Naming>,
+    // The root folder's own row is collapsed
+    collapsed: bool,
+    // Last failed file operation
+    error: Option<String>,
+}
+
+impl FileTree {
+    pub fn new(root: PathBuf, settings: &Settings) -> Self {
+        let root = std::path::absolute(&root).unwrap_or(root);
+        let expanded = settings.explorer_expanded.iter().filter(|dir| dir.starts_with(&root)).cloned().collect();
+        Self {
+            root,
+            children: HashMap::new(),
+            expanded,
+            reveal: false,
+            active: None,
+            naming: None,
+            collapsed: false,
+            error: None,
+        }
+    }
+
+    /// Forgets the listings so they are read again; expanded folders stay expanded.
+    pub fn refresh(&mut self) {
+        self.children.clear();
+    }
+
+    /// Re-reads the folders affected by a change to `path`, an absolute path from the file watcher.
+    pub fn changed(&mut self, path: &Path) {
+        if !path.starts_with(&self.root) {
+            return;
+        }
+        let name = path.file_name().unwrap_or_default();
+        if name == ".gitignore" || name == ".ignore" {
+            // Ignore files change what every folder below them lists
+            self.refresh();
+            return;
+        }
+        if let Some(parent) = path.parent() {
+            self.children.remove(parent);
+        }
+        self.children.remove(path);
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The root folder's name.
+    pub fn name(&self) -> String {
+        self.root.file_name().map_or_else(|| self.root.display().to_string(), |name| name.to_string_lossy().to_string())
+    }
+
+    /// Expands the folders above `path` and scrolls to it. The settings are updated but left for
+    /// the caller to save.
+    pub fn reveal(&mut self, path: &Path, settings: &mut Settings) {
+        let Some(path) = self.tree_path(path) else {
+            return;
+        };
+        for dir in path.ancestors().skip(1) {
+            if dir == self.root || !dir.starts_with(&self.root) {
+                break;
+            }
+            self.expanded.insert(dir.to_path_buf());
+        }
+        self.collapsed = false;
+        self.reveal = true;
+        self.store_expanded(settings);
+    }
+
+    // `path` spelled like the tree's own paths, if it is inside the root
+    fn tree_path(&self, path: &Path) -> Option<PathBuf> {
+        if path.starts_with(&self.root) {
+            return Some(path.to_path_buf());
+        }
+        let root = self.root.canonicalize().ok()?;
+        let path = path.canonicalize().ok()?;
+        Some(self.root.join(path.strip_prefix(root).ok()?))
+    }
+
+    // Replaces this tree's folders in the settings, keeping the other workspace folders' ones
+    fn store_expanded(&self, settings: &mut Settings) {
+        let expanded = &mut settings.explorer_expanded;
+        expanded.retain(|dir| !dir.starts_with(&self.root));
+        expanded.extend(self.expanded.iter().cloned());
+        expanded.sort();
+    }
+
+    fn entries(&mut self, dir: &Path, settings: &Settings) -> Vec<WalkEntry> {
+        if let Some(entries) = self.children.get(dir) {
+            return entries.clone();
+        }
+        let rules = IgnoreRules::new(&self.root, &settings.exclude_patterns);
+        let mut entries: Vec<WalkEntry> = Walk::dir(&self.root, dir, &rules, settings.show_ignored)
+            .filter(|entry| {
+                let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+                !name.starts_with('.') && name != "settings.json"
+            })
+            .collect();
+        entries.sort_by_cached_key(|entry| (!entry.is_dir, entry.path.file_name().unwrap_or_default().to_string_lossy().to_lowercase()));
+        self.children.insert(dir.to_path_buf(), entries.clone());
+        entries
+    }
+
+    /// Draws the root folder's row and, unless it is collapsed, the tree below it, then runs
+    /// the context menu commands.
+    pub fn show(&mut self, ui: &mut Ui, settings: &mut Settings, active: Option<&Path>) -> Vec<TreeEvent> {
+        let active = active.and_then(|path| match &self.active {
+            Some((cached, resolved)) if cached == path => resolved.clone(),
+            _ => {
+                let resolved = self.tree_path(path);
+                self.active = Some((path.to_path_buf(), resolved.clone()));
+                resolved
+            }
+        });
+        let mut events = Vec::new();
+        let mut command = None;
+        let root = self.root.clone();
+        let name = self.name();
+        let arrow = if self.collapsed { "⏵" } else { "⏷" };
+        let header = ui
+            .selectable_label(false, egui::RichText::new(format!("{arrow} {name}")).strong())
+            .on_hover_text(root.display().to_string());
+        if header.clicked() {
+            self.collapsed = !self.collapsed;
+        }
+        header.context_menu(|ui| {
+            if ui.button("New File").clicked() {
+                command = Some(Command::NewFile(root.clone()));
+                ui.close_menu();
+            }
+            if ui.button("New Folder").clicked() {
+                command = Some(Command::NewFolder(root.clone()));
+                ui.close_menu();
+            }
+            ui.separator();
+            if ui.button("Copy Path").clicked() {
+                ui.ctx().copy_text(root.display().to_string());
+                ui.close_menu();
+            }
+            if ui.button("Remove Folder from Workspace").clicked() {
+                events.push(TreeEvent::RemoveRoot(root.clone()));
+                ui.close_menu();
+            }
+        });
+
+        if let Some(error) = &self.error {
+            let mut dismiss = false;
+            ui.horizontal(|ui| {
+                dismiss = ui.small_button("×").clicked();
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            });
+            if dismiss {
+                self.error = None;
+            }
+        }
+
+        if !self.collapsed {
+            self.show_dir(ui, &root, 0, settings, active.as_deref(), &mut events, &mut command);
+        }
+        self.reveal = false;
+
+        if let Some(command) = command {
+            self.run(command, settings, &mut events);
+        }
+        events
+    }
+
+    fn run(&mut self, command: Command, settings: &mut Settings, events: &mut Vec<TreeEvent>) {
+        let (target, name) = match command {
+            Command::NewFile(dir) => (NameTarget::NewFile(dir), String::new()),
+            Command::NewFolder(dir) => (NameTarget::NewFolder(dir), String::new()),
+            Command::Rename(path) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                (NameTarget::Rename(path), name)
+            }
+            Command::Duplicate(path) => {
+                match duplicate(&path) {
+                    Ok(copy) if copy.is_file() => events.push(TreeEvent::Open(copy)),
+                    Ok(_) => {}
+                    Err(error) => self.error = Some(format!("Couldn't duplicate {}: {}", path.display(), error)),
+                }
+                self.refresh();
+                return;
+            }
+            Command::Delete(path) => {
+                match trash::delete(&path) {
+                    Ok(()) => events.push(TreeEvent::Deleted(path)),
+                    Err(error) => self.error = Some(format!("Couldn't move {} to the trash: {}", path.display(), error)),
+                }
+                self.refresh();
+                return;
+            }
+        };
+        // New entries show up at the top of their folder, so it has to be open
+        if let NameTarget::NewFile(dir) | NameTarget::NewFolder(dir) = &target {
+            if *dir == self.root {
+                self.collapsed = false;
+            } else {
+                self.expanded.insert(dir.clone());
+                self.store_expanded(settings);
+                events.push(TreeEvent::ExpandedChanged);
+            }
+        }
+        self.naming = Some(Naming { target, name, focus: true });
+    }
+
+    // Creates or renames the entry being named
+    fn finish_naming(&mut self, events: &mut Vec<TreeEvent>) {
+        let Some(naming) = self.naming.take() else {
+            return;
+        };
+        let name = naming.name.trim();
+        if name.is_empty() {
+            return;
+        }
+        let result = match (valid_name(name), &naming.target) {
+            (Err(error), _) => Err(error),
+            (Ok(()), NameTarget::NewFile(dir)) => {
+                let path = dir.join(name);
+                create_new(&path, false).map(|()| events.push(TreeEvent::Open(path)))
+            }
+            (Ok(()), NameTarget::NewFolder(dir)) => create_new(&dir.join(name), true),
+            (Ok(()), NameTarget::Rename(from)) => {
+                let to = from.with_file_name(name);
+                if to == *from {
+                    return;
+                }
+                rename(from, &to).map(|()| {
+                    if self.expanded.remove(from) {
+                        self.expanded.insert(to.clone());
+                    }
+                    events.push(TreeEvent::Renamed { from: from.clone(), to });
+                })
+            }
+        };
+        if let Err(error) = result {
+            self.error = Some(match &naming.target {
+                NameTarget::Rename(from) => format!("Couldn't rename {}: {}", from.display(), error),
+                _ => format!("Couldn't create {name}: {error}"),
+            });
+        }
+        self.refresh();
+    }
+
+    // Text field for the entry being named; Enter confirms, Escape or clicking away cancels
+    fn show_naming(&mut self, ui: &mut Ui, events: &mut Vec<TreeEvent>) {
+        let Some(naming) = &mut self.naming else {
+            return;
+        };
+        let response = ui.add(egui::TextEdit::singleline(&mut naming.name).desired_width(ui.available_width()));
+        if naming.focus {
+            naming.focus = false;
+            response.request_focus();
+        }
+        if response.lost_focus() {
+            if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.finish_naming(events);
+            } else {
+                self.naming = None;
+            }
+        }
+    }
+
+    fn is_naming(&self, target: &NameTarget) -> bool {
+        self.naming.as_ref().is_some_and(|naming| naming.target == *target)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn show_dir(
+        &mut self,
+        ui: &mut Ui,
+        dir: &Path,
+        depth: usize,
+        settings: &mut Settings,
+        active: Option<&Path>,
+        events: &mut Vec<TreeEvent>,
+        command: &mut Option<Command>,
+    ) {
+        let new_entry = [NameTarget::NewFolder(dir.to_path_buf()), NameTarget::NewFile(dir.to_path_buf())]
+            .into_iter()
+            .find(|target| self.is_naming(target));
+        if let Some(target) = new_entry {
+            ui.horizontal(|ui| {
+                ui.add_space(depth as f32 * 12.0);
+                ui.label(if matches!(target, NameTarget::NewFolder(_)) { "🗀" } else { "🗋" });
+                self.show_naming(ui, events);
+            });
+        }
+
+        for entry in self.entries(dir, settings) {
+            let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+            let expanded = entry.is_dir && self.expanded.contains(&entry.path);
+            let label = match (entry.is_dir, expanded) {
+                (true, true) => format!("⏷ 🗁 {name}"),
+                (true, false) => format!("⏵ 🗀 {name}"),
+                (false, _) => format!("🗋 {name}"),
+            };
+            let mut text = egui::RichText::new(label);
+            if entry.ignored {
+                text = text.color(ui.visuals().weak_text_color());
+            }
+
+            let is_active = active == Some(entry.path.as_path());
+            // Files line up with folder names, past the arrow
+            let indent = depth as f32 * 12.0 + if entry.is_dir { 0.0 } else { 14.0 };
+            if self.is_naming(&NameTarget::Rename(entry.path.clone())) {
+                ui.horizontal(|ui| {
+                    ui.add_space(indent);
+                    self.show_naming(ui, events);
+                });
+                continue;
+            }
+            let response = ui
+                .horizontal(|ui| {
+                    ui.add_space(indent);
+                    ui.selectable_label(is_active, text)
+                })
+                .inner;
+            if is_active && self.reveal {
+                response.scroll_to_me(Some(egui::Align::Center));
+            }
+            let response = response.on_hover_text(entry.path.display().to_string());
+            response.context_menu(|ui| {
+                let parent = if entry.is_dir { entry.path.clone() } else { dir.to_path_buf() };
+                let mut pick = |ui: &mut Ui, label: &str, chosen: Command| {
+                    if ui.button(label).clicked() {
+                        *command = Some(chosen);
+                        ui.close_menu();
+                    }
+                };
+                pick(ui, "New File", Command::NewFile(parent.clone()));
+                pick(ui, "New Folder", Command::NewFolder(parent));
+                ui.separator();
+                pick(ui, "Rename", Command::Rename(entry.path.clone()));
+                pick(ui, "Duplicate", Command::Duplicate(entry.path.clone()));
+                pick(ui, "Delete", Command::Delete(entry.path.clone()));
+                ui.separator();
+                if ui.button("Copy Path").clicked() {
+                    let path = std::path::absolute(&entry.path).unwrap_or_else(|_| entry.path.clone());
+                    ui.ctx().copy_text(path.display().to_string());
+                    ui.close_menu();
+                }
+                if ui.button("Copy Relative Path").clicked() {
+                    let relative = entry.path.strip_prefix(&self.root).unwrap_or(&entry.path);
+                    ui.ctx().copy_text(relative.display().to_string());
+                    ui.close_menu();
+                }
+            });
+            if response.clicked() {
+                if !entry.is_dir {
+                    events.push(TreeEvent::Open(entry.path.clone()));
+                } else {
+                    if expanded {
+                        self.expanded.remove(&entry.path);
+                    } else {
+                        self.expanded.insert(entry.path.clone());
+                    }
+                    self.store_expanded(settings);
+                    events.push(TreeEvent::ExpandedChanged);
+                }
+            }
+            if expanded {
+                self.show_dir(ui, &entry.path, depth + 1, settings, active, events, command);
+            }
+        }
+    }
+}
+
+// A name typed into the tree has to be a single entry in the folder it was typed in
+fn valid_name(name: &str) -> io::Result<()> {
+    let reason = if name == "." || name == ".." {
+        "\".\" and \"..\" aren't names of their own"
+    } else if name.chars().any(std::path::is_separator) {
+        "names can't contain path separators"
+    } else {
+        return Ok(());
+    };
+    Err(io::Error::new(io::ErrorKind::InvalidInput, reason))
+}
+
+// Fails instead of overwriting an existing entry
+fn create_new(path: &Path, is_dir: bool) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if is_dir {
+        std::fs::create_dir(path)
+    } else {
+        std::fs::File::create_new(path).map(drop)
+    }
+}
+
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+    std::fs::rename(from, to)
+}
+
+// Copies a file or folder next to itself as "name copy", "name copy 2", ...
+fn duplicate(path: &Path) -> io::Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let copy = (1..)
+        .map(|n| match n {
+            1 => path.with_file_name(format!("{stem} copy{extension}")),
+            n => path.with_file_name(format!("{stem} copy {n}{extension}")),
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_default();
+    copy_recursively(path, &copy)?;
+    Ok(copy)
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    let file_type = std::fs::symlink_metadata(from)?.file_type();
+    if file_type.is_symlink() {
+        return copy_link(from, to);
+    }
+    if !file_type.is_dir() {
+        return std::fs::copy(from, to).map(drop);
+    }
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+// Links are copied as links, so one pointing back up the tree can't make the copy endless
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    let target = std::fs::read_link(from)?;
+    if from.is_dir() {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_tree(name: &str) -> FileTree {
+        let root = std::env::temp_dir().join(format!("clawpad-explorer-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        FileTree::new(root, &Settings::default())
+    }
+
+    fn name(tree: &mut FileTree, target: NameTarget, name: &str) -> Vec<TreeEvent> {
+        let mut events = Vec::new();
+        tree.error = None;
+        tree.naming = Some(Naming { target, name: name.to_string(), focus: false });
+        tree.finish_naming(&mut events);
+        events
+    }
+
+    #[test]
+    fn new_files_open_and_existing_entries_are_kept() {
+        let mut tree = temp_tree("create");
+        let root = tree.root.clone();
+        let events = name(&mut tree, NameTarget::NewFile(root.clone()), " notes.txt ");
+        assert!(matches!(events.as_slice(), [TreeEvent::Open(path)] if *path == root.join("notes.txt")));
+        name(&mut tree, NameTarget::NewFolder(root.clone()), "sub");
+        assert!(root.join("sub").is_dir());
+
+        fs::write(root.join("notes.txt"), "keep").unwrap();
+        assert!(name(&mut tree, NameTarget::NewFile(root.clone()), "notes.txt").is_empty());
+        assert!(tree.error.is_some());
+        assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "keep");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn names_must_stay_in_their_folder() {
+        let mut tree = temp_tree("names");
+        let root = tree.root.clone();
+        fs::write(root.join("a.txt"), "").unwrap();
+        for bad in ["../escape.txt", "sub/file.txt", ".", ".."] {
+            assert!(name(&mut tree, NameTarget::NewFile(root.clone()), bad).is_empty(), "{bad}");
+            assert!(tree.error.is_some(), "{bad}");
+            assert!(name(&mut tree, NameTarget::Rename(root.join("a.txt")), bad).is_empty(), "{bad}");
+        }
+        assert!(!root.parent().unwrap().join("escape.txt").exists());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rename_moves_the_entry_unless_the_name_is_taken() {
+        let mut tree = temp_tree("rename");
+        let root = tree.root.clone();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        let events = name(&mut tree, NameTarget::Rename(root.join("a.txt")), "c.txt");
+        assert!(matches!(events.as_slice(), [TreeEvent::Renamed { from, to }]
+            if *from == root.join("a.txt") && *to == root.join("c.txt")));
+        assert!(name(&mut tree, NameTarget::Rename(root.join("c.txt")), "b.txt").is_empty());
+        assert!(tree.error.is_some());
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn duplicate_picks_a_free_name_and_copies_folders() {
+        let tree = temp_tree("duplicate");
+        let root = tree.root.clone();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        assert_eq!(duplicate(&root.join("a.txt")).unwrap(), root.join("a copy.txt"));
+        assert_eq!(duplicate(&root.join("a.txt")).unwrap(), root.join("a copy 2.txt"));
+        assert_eq!(fs::read_to_string(root.join("a copy 2.txt")).unwrap(), "a");
+
+        fs::create_dir_all(root.join("dir/inner")).unwrap();
+        fs::write(root.join("dir/inner/f.rs"), "f").unwrap();
+        assert_eq!(duplicate(&root.join("dir")).unwrap(), root.join("dir copy"));
+        assert_eq!(fs::read_to_string(root.join("dir copy/inner/f.rs")).unwrap(), "f");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn duplicate_copies_links_as_links() {
+        let tree = temp_tree("links");
+        let root = tree.root.clone();
+        fs::create_dir(root.join("dir")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("dir/up")).unwrap();
+        duplicate(&root.join("dir")).unwrap();
+        assert_eq!(fs::read_link(root.join("dir copy/up")).unwrap(), Path::new(".."));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,416 @@
+use crate::document::Document;
+use egui::{Key, Ui};
+use regex::{Captures, Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind};
+use ropey::Rope;
+use std::ops::Range;
+
+// Bytes of text searched at once when a match can't span lines
+const BATCH_BYTES: usize = 64 * 1024;
+
+/// Search options shared by the find bar and anything else that matches text.
+#[derive(Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+}
+
+impl SearchOptions {
+    /// Compiles `query` into a regex honoring the options; plain queries are escaped.
+    pub fn build(&self, query: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(&self.pattern(query))
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+    }
+
+    fn pattern(&self, query: &str) -> String {
+        let pattern = if self.regex { query.to_string() } else { regex::escape(query) };
+        if self.whole_word { format!(r"\b(?:{pattern})\b") } else { pattern }
+    }
+
+    // Whether a match for `query` can contain a line break; true if it can't be parsed
+    fn spans_lines(&self, query: &str) -> bool {
+        regex_syntax::ParserBuilder::new()
+            .multi_line(true)
+            .build()
+            .parse(&self.pattern(query))
+            .map_or(true, |hir| matches_line_break(&hir))
+    }
+
+    /// Replacement text for a match, expanding `$1` and `${name}` in regex mode.
+    pub fn expand(&self, captures: &Captures, replacement: &str) -> String {
+        let mut expanded = String::new();
+        if self.regex {
+            captures.expand(replacement, &mut expanded);
+        } else {
+            expanded.push_str(replacement);
+        }
+        expanded
+    }
+}
+
+/// Find/replace bar for the active document.
+#[derive(Default)]
+pub struct FindBar {
+    pub visible: bool,
+    show_replace: bool,
+    query: String,
+    replacement: String,
+    options: SearchOptions,
+    regex: Option<Regex>,
+    error: Option<String>,
+    // Char ranges of the matches in the document they were computed for
+    matches: Vec<Range<usize>>,
+    // (document id, revision) the matches belong to; None forces a new search
+    computed_for: Option<(u64, u64)>,
+    focus_query: bool,
+}
+
+impl FindBar {
+    /// Opens the bar, seeding the query from a single-line selection.
+    pub fn open(&mut self, doc: &Document, replace: bool) {
+        let selected = doc.rope.slice(doc.selected_range()).to_string();
+        if !selected.is_empty() && !selected.contains('\n') {
+            self.query = selected;
+            self.computed_for = None;
+        }
+        self.visible = true;
+        self.show_replace |= replace;
+        self.focus_query = true;
+    }
+
+    /// The matches in `doc`, or none once it has changed since they were found.
+    pub fn matches(&self, doc: &Document) -> &[Range<usize>] {
+        if self.visible && self.computed_for == Some((doc.id, doc.revision())) { &self.matches } else { &[] }
+    }
+
+    // Re-runs the search when the query, the options or the document changed
+    fn update(&mut self, doc: &mut Document) {
+        let key = (doc.id, doc.revision());
+        if self.computed_for == Some(key) {
+            return;
+        }
+        self.computed_for = Some(key);
+        self.matches.clear();
+        self.error = None;
+        self.regex = None;
+        if self.query.is_empty() {
+            return;
+        }
+
+        match self.options.build(&self.query) {
+            Ok(regex) => {
+                // Copying the whole text on every edit is only worth it for matches across lines
+                let bytes: Vec<Range<usize>> = if self.options.spans_lines(&self.query) {
+                    regex.find_iter(doc.text())
+                        .filter(|m| !m.is_empty())
+                        .map(|m| m.range())
+                        .collect()
+                } else {
+                    find_by_lines(&doc.rope, &regex)
+                };
+                self.matches = bytes.into_iter()
+                    .map(|range| doc.rope.byte_to_char(range.start)..doc.rope.byte_to_char(range.end))
+                    .collect();
+                self.regex = Some(regex);
+            }
+            Err(err) => self.error = Some(err.to_string()),
+        }
+    }
+
+    // Match under or after the primary selection
+    fn current(&self, doc: &Document) -> Option<usize> {
+        let start = doc.selected_range().start;
+        let index = self.matches.partition_point(|m| m.start < start);
+        (!self.matches.is_empty()).then_some(index % self.matches.len())
+    }
+
+    pub fn find_next(&mut self, doc: &mut Document) {
+        self.update(doc);
+        let end = doc.selected_range().end;
+        let index = self.matches.partition_point(|m| m.start < end);
+        if let Some(found) = self.matches.get(index).or(self.matches.first()) {
+            doc.set_selection((found.start, found.end));
+        }
+    }
+
+    pub fn find_previous(&mut self, doc: &mut Document) {
+        self.update(doc);
+        let start = doc.selected_range().start;
+        let index = self.matches.partition_point(|m| m.start < start);
+        let found = index.checked_sub(1).and_then(|i| self.matches.get(i)).or(self.matches.last());
+        if let Some(found) = found {
+            doc.set_selection((found.start, found.end));
+        }
+    }
+
+    // Replacement text for the match starting at `byte`
+    fn replacement_at(&self, regex: &Regex, text: &str, byte: usize) -> Option<(Range<usize>, String)> {
+        let captures = regex.captures_at(text, byte)?;
+        let whole = captures.get(0)?;
+        Some((whole.range(), self.options.expand(&captures, &self.replacement)))
+    }
+
+    /// Replaces the selected match and moves on to the next one.
+    fn replace(&mut self, doc: &mut Document) {
+        self.update(doc);
+        let selected = doc.selected_range();
+        let (Some(regex), true) = (self.regex.clone(), self.matches.contains(&selected)) else {
+            self.find_next(doc);
+            return;
+        };
+        let byte = doc.rope.char_to_byte(selected.start);
+        let Some((range, replacement)) = self.replacement_at(&regex, doc.text(), byte) else {
+            return;
+        };
+        let range = doc.rope.byte_to_char(range.start)..doc.rope.byte_to_char(range.end);
+
+        let before = doc.cursor_state();
+        doc.history.break_group();
+        doc.delete(range.clone());
+        doc.insert(range.start, &replacement);
+        let caret = range.start + replacement.chars().count();
+        doc.set_selection((caret, caret));
+        doc.commit_edits(before);
+        self.find_next(doc);
+    }
+
+    /// Replaces every match as a single undo step.
+    fn replace_all(&mut self, doc: &mut Document) {
+        self.update(doc);
+        let Some(regex) = self.regex.clone() else {
+            return;
+        };
+        let text = doc.text();
+        let edits: Vec<(Range<usize>, String)> = regex.find_iter(text)
+            .filter(|m| !m.is_empty())
+            .filter_map(|m| self.replacement_at(&regex, text, m.start()))
+            .collect();
+        if edits.is_empty() {
+            return;
+        }
+
+        let before = doc.cursor_state();
+        doc.history.break_group();
+        // Back to front so earlier ranges stay valid
+        for (range, replacement) in edits.iter().rev() {
+            let start = doc.rope.byte_to_char(range.start);
+            doc.delete(start..doc.rope.byte_to_char(range.end));
+            doc.insert(start, replacement);
+        }
+        let caret = doc.selected_range().start.min(doc.rope.len_chars());
+        doc.set_selection((caret, caret));
+        doc.commit_edits(before);
+        doc.history.break_group();
+    }
+
+    pub fn show(&mut self, ui: &mut Ui, doc: &mut Document) {
+        self.update(doc);
+
+        ui.horizontal(|ui| {
+            let toggle = if self.show_replace { "⏷" } else { "⏵" };
+            if ui.small_button(toggle).on_hover_text("Toggle Replace").clicked() {
+                self.show_replace = !self.show_replace;
+            }
+
+            let response = ui.add(egui::TextEdit::singleline(&mut self.query).hint_text("Find").desired_width(240.0));
+            if self.focus_query {
+                self.focus_query = false;
+                response.request_focus();
+            }
+            if response.changed() {
+                self.computed_for = None;
+                self.update(doc);
+                // Jump to the first match from the cursor while typing
+                if let Some(index) = self.current(doc) {
+                    let found = self.matches[index].clone();
+                    doc.set_selection((found.start, found.end));
+                }
+            }
+            // Single-line edits give up focus on Enter/Escape
+            if response.lost_focus() {
+                if ui.input(|i| i.key_pressed(Key::Enter)) {
+                    if ui.input(|i| i.modifiers.shift) {
+                        self.find_previous(doc);
+                    } else {
+                        self.find_next(doc);
+                    }
+                    response.request_focus();
+                } else if ui.input(|i| i.key_pressed(Key::Escape)) {
+                    self.visible = false;
+                }
+            }
+
+            let before = self.options.clone();
+            ui.toggle_value(&mut self.options.case_sensitive, "Aa").on_hover_text("Match Case");
+            ui.toggle_value(&mut self.options.whole_word, "ab").on_hover_text("Match Whole Word");
+            ui.toggle_value(&mut self.options.regex, ".*").on_hover_text("Use Regular Expression");
+            if self.options != before {
+                self.computed_for = None;
+                self.update(doc);
+            }
+
+            match &self.error {
+                Some(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, "Invalid regex").on_hover_text(error);
+                }
+                None if self.query.is_empty() => {}
+                None if self.matches.is_empty() => {
+                    ui.label("No results");
+                }
+                None => {
+                    let selected = doc.selected_range();
+                    let total = self.matches.len();
+                    match self.matches.binary_search_by_key(&selected.start, |m| m.start) {
+                        Ok(index) if self.matches[index] == selected => ui.label(format!("{} of {}", index + 1, total)),
+                        _ => ui.label(format!("? of {total}")),
+                    };
+                }
+            }
+
+            if ui.small_button("↑").on_hover_text("Previous Match (Shift+Enter)").clicked() {
+                self.find_previous(doc);
+            }
+            if ui.small_button("↓").on_hover_text("Next Match (Enter)").clicked() {
+                self.find_next(doc);
+            }
+            if ui.small_button("×").on_hover_text("Close (Escape)").clicked() {
+                self.visible = false;
+            }
+        });
+
+        if self.show_replace {
+            ui.horizontal(|ui| {
+                ui.add_space(ui.spacing().interact_size.y + ui.spacing().item_spacing.x);
+                let hint = if self.options.regex { "Replace ($1 for groups)" } else { "Replace" };
+                let response = ui.add(egui::TextEdit::singleline(&mut self.replacement).hint_text(hint).desired_width(240.0));
+                if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                    self.replace(doc);
+                    response.request_focus();
+                }
+                let has_matches = !self.matches.is_empty();
+                if ui.add_enabled(has_matches, egui::Button::new("Replace")).clicked() {
+                    self.replace(doc);
+                }
+                if ui.add_enabled(has_matches, egui::Button::new("Replace All")).clicked() {
+                    self.replace_all(doc);
+                }
+            });
+        }
+    }
+}
+
+// Byte ranges of the non-empty matches of a regex that can't match a line break, searched a
+// batch of whole lines at a time
+fn find_by_lines(rope: &Rope, regex: &Regex) -> Vec<Range<usize>> {
+    let mut found = Vec::new();
+    let mut batch = String::new();
+    let mut offset = 0;
+    let mut search = |text: &str, offset: usize| {
+        found.extend(regex.find_iter(text)
+            .filter(|m| !m.is_empty())
+            .map(|m| m.start() + offset..m.end() + offset));
+    };
+    for chunk in rope.chunks() {
+        batch.push_str(chunk);
+        if batch.len() >= BATCH_BYTES && let Some(newline) = batch.rfind('\n') {
+            search(&batch[..=newline], offset);
+            batch.drain(..=newline);
+            offset += newline + 1;
+        }
+    }
+    search(&batch, offset);
+    found
+}
+
+fn matches_line_break(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => false,
+        HirKind::Literal(literal) => literal.0.contains(&b'\n'),
+        HirKind::Class(Class::Unicode(class)) => class.ranges().iter().any(|r| r.start() <= '\n' && '\n' <= r.end()),
+        HirKind::Class(Class::Bytes(class)) => class.ranges().iter().any(|r| r.start() <= b'\n' && b'\n' <= r.end()),
+        HirKind::Repetition(repetition) => matches_line_break(&repetition.sub),
+        HirKind::Capture(capture) => matches_line_break(&capture.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(matches_line_break),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_bar(query: &str, replacement: &str, regex: bool) -> FindBar {
+        FindBar {
+            visible: true,
+            query: query.to_string(),
+            replacement: replacement.to_string(),
+            options: SearchOptions { regex, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn replace_all_expands_groups_in_one_undo_step() {
+        let mut doc = Document::new("a1 b2 a3".to_string(), None);
+        let mut bar = find_bar(r"a(\d)", "x$1", true);
+        bar.replace_all(&mut doc);
+        assert_eq!(doc.text(), "x1 b2 x3");
+        doc.undo();
+        assert_eq!(doc.text(), "a1 b2 a3");
+        assert!(!doc.history.can_undo());
+    }
+
+    #[test]
+    fn replace_swaps_the_selected_match_and_selects_the_next() {
+        let mut doc = Document::new("foo bar foo".to_string(), None);
+        doc.set_selection((0, 3));
+        let mut bar = find_bar("foo", "baz", false);
+        bar.replace(&mut doc);
+        assert_eq!(doc.text(), "baz bar foo");
+        assert_eq!(doc.selected_range(), 8..11);
+    }
+
+    #[test]
+    fn replace_without_a_selected_match_only_finds_the_next() {
+        let mut doc = Document::new("foo bar foo".to_string(), None);
+        let mut bar = find_bar("foo", "baz", false);
+        bar.replace(&mut doc);
+        assert_eq!(doc.text(), "foo bar foo");
+        assert_eq!(doc.selected_range(), 0..3);
+    }
+
+    #[test]
+    fn matches_are_dropped_once_the_document_changes() {
+        let mut doc = Document::new("foo foo".to_string(), None);
+        let mut bar = find_bar("foo", "", false);
+        bar.update(&mut doc);
+        assert_eq!(bar.matches(&doc), [0..3, 4..7]);
+        doc.delete(0..7);
+        assert!(bar.matches(&doc).is_empty());
+    }
+
+    #[test]
+    fn searching_by_lines_finds_what_searching_the_text_does() {
+        let text = "héllo wörld\n".repeat(20_000);
+        let rope = Rope::from_str(&text);
+        assert!(rope.chunks().count() > 1);
+        let regex = SearchOptions { regex: true, ..Default::default() }.build("^h|wörld$").unwrap();
+        let expected: Vec<Range<usize>> = regex.find_iter(&text).map(|m| m.range()).collect();
+        assert_eq!(expected.len(), 40_000);
+        assert_eq!(find_by_lines(&rope, &regex), expected);
+    }
+
+    #[test]
+    fn spans_lines_spots_patterns_that_match_a_line_break() {
+        let plain = SearchOptions::default();
+        let regex = SearchOptions { regex: true, ..Default::default() };
+        assert!(!plain.spans_lines("a\\sb"));
+        assert!(!regex.spans_lines("a.b"));
+        assert!(regex.spans_lines(r"a\sb"));
+        assert!(regex.spans_lines("[^x]"));
+        assert!(regex.spans_lines(r"a\nb"));
+        assert!(regex.spans_lines("("));
+    }
+}
@@ -0,0 +1,238 @@
+use ropey::Rope;
+use std::time::{Duration, Instant};
+
+// Edits closer together than this are merged into one undo step
+const GROUP_TIMEOUT: Duration = Duration::from_millis(1000);
+const MAX_UNDO_STEPS: usize = 1000;
+
+/// Anchor and head of a selection, in char indices.
+pub type Selection = (usize, usize);
+
+/// Every selection, plus the index of the primary one.
+pub type Cursors = (Vec<Selection>, usize);
+
+#[derive(Clone, Debug)]
+pub enum EditOp {
+    Insert { at: usize, text: String },
+    Delete { at: usize, text: String },
+}
+
+impl EditOp {
+    pub fn apply(&self, rope: &mut Rope) {
+        match self {
+            EditOp::Insert { at, text } => rope.insert(*at, text),
+            EditOp::Delete { at, text } => rope.remove(*at..*at + text.chars().count()),
+        }
+    }
+
+    fn inverse(&self) -> EditOp {
+        match self {
+            EditOp::Insert { at, text } => EditOp::Delete { at: *at, text: text.clone() },
+            EditOp::Delete { at, text } => EditOp::Insert { at: *at, text: text.clone() },
+        }
+    }
+}
+
+/// A group of edits that is undone and redone as a single step.
+pub struct Transaction {
+    ops: Vec<EditOp>,
+    // How many of the ops came from the latest edit: one per cursor when typing
+    last_edit_ops: usize,
+    cursors_before: Cursors,
+    cursors_after: Cursors,
+}
+
+impl Transaction {
+    // Typing bursts: every cursor's single-line insert continuing its previous one, or its
+    // delete at the same spot. Ops are applied front to back, so each one is compared after
+    // undoing the shift from the new ops before it.
+    fn can_merge(&self, next: &[EditOp]) -> bool {
+        if next.is_empty() || next.len() != self.last_edit_ops {
+            return false;
+        }
+        let last_edit = &self.ops[self.ops.len() - self.last_edit_ops..];
+        let mut shift = 0isize;
+        for (last, op) in last_edit.iter().zip(next) {
+            let continues = match (last, op) {
+                (EditOp::Insert { at, text }, EditOp::Insert { at: next_at, text: next_text }) => {
+                    *next_at as isize - shift == (at + text.chars().count()) as isize && !next_text.contains('\n')
+                }
+                (EditOp::Delete { at, .. }, EditOp::Delete { at: next_at, text: next_text }) => {
+                    // Backspace walks left, Delete stays in place
+                    let next_at = *next_at as isize - shift;
+                    next_at + next_text.chars().count() as isize == *at as isize || next_at == *at as isize
+                }
+                _ => false,
+            };
+            if !continues {
+                return false;
+            }
+            shift += match op {
+                EditOp::Insert { text, .. } => text.chars().count() as isize,
+                EditOp::Delete { text, .. } => -(text.chars().count() as isize),
+            };
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    last_edit: Option<Instant>,
+}
+
+impl History {
+    pub fn record(&mut self, ops: Vec<EditOp>, cursors_before: Cursors, cursors_after: Cursors) {
+        if ops.is_empty() {
+            return;
+        }
+        self.redo_stack.clear();
+
+        let now = Instant::now();
+        let recent = self.last_edit.is_some_and(|t| now.duration_since(t) < GROUP_TIMEOUT);
+        self.last_edit = Some(now);
+
+        if recent
+            && let Some(top) = self.undo_stack.last_mut()
+            && top.can_merge(&ops)
+        {
+            top.last_edit_ops = ops.len();
+            top.ops.extend(ops);
+            top.cursors_after = cursors_after;
+            return;
+        }
+
+        self.undo_stack.push(Transaction { last_edit_ops: ops.len(), ops, cursors_before, cursors_after });
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// Closes the current group so the next edit starts a new undo step.
+    pub fn break_group(&mut self) {
+        self.last_edit = None;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Pops the last step, returning the operations that revert it (in order) and the cursors to restore.
+    pub fn undo(&mut self) -> Option<(Vec<EditOp>, Cursors)> {
+        let transaction = self.undo_stack.pop()?;
+        let ops = transaction.ops.iter().rev().map(EditOp::inverse).collect();
+        let cursors = transaction.cursors_before.clone();
+        self.redo_stack.push(transaction);
+        self.break_group();
+        Some((ops, cursors))
+    }
+
+    pub fn redo(&mut self) -> Option<(Vec<EditOp>, Cursors)> {
+        let transaction = self.redo_stack.pop()?;
+        let ops = transaction.ops.clone();
+        let cursors = transaction.cursors_after.clone();
+        self.undo_stack.push(transaction);
+        self.break_group();
+        Some((ops, cursors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(at: usize, text: &str) -> EditOp {
+        EditOp::Insert { at, text: text.to_string() }
+    }
+
+    fn delete(at: usize, text: &str) -> EditOp {
+        EditOp::Delete { at, text: text.to_string() }
+    }
+
+    fn record(history: &mut History, ops: Vec<EditOp>) {
+        history.record(ops, (Vec::new(), 0), (Vec::new(), 0));
+    }
+
+    fn steps(history: &mut History) -> usize {
+        std::iter::from_fn(|| history.undo()).count()
+    }
+
+    #[test]
+    fn typing_is_grouped() {
+        let mut history = History::default();
+        for (i, ch) in ["a", "b", "c"].into_iter().enumerate() {
+            record(&mut history, vec![insert(i, ch)]);
+        }
+        assert_eq!(steps(&mut history), 1);
+    }
+
+    #[test]
+    fn new_lines_and_jumps_start_a_new_step() {
+        let mut history = History::default();
+        record(&mut history, vec![insert(0, "a")]);
+        record(&mut history, vec![insert(1, "\n")]);
+        record(&mut history, vec![insert(0, "b")]);
+        assert_eq!(steps(&mut history), 3);
+    }
+
+    #[test]
+    fn break_group_starts_a_new_step() {
+        let mut history = History::default();
+        record(&mut history, vec![insert(0, "a")]);
+        history.break_group();
+        record(&mut history, vec![insert(1, "b")]);
+        assert_eq!(steps(&mut history), 2);
+    }
+
+    #[test]
+    fn backspace_and_delete_are_grouped() {
+        let mut history = History::default();
+        record(&mut history, vec![delete(4, "e")]);
+        record(&mut history, vec![delete(3, "d")]);
+        record(&mut history, vec![delete(3, "x")]);
+        assert_eq!(steps(&mut history), 1);
+    }
+
+    #[test]
+    fn typing_with_several_cursors_is_grouped() {
+        // Carets at 5 and 10; each op sees the text as changed by the ones before it
+        let mut history = History::default();
+        record(&mut history, vec![insert(5, "a"), insert(11, "a")]);
+        record(&mut history, vec![insert(6, "b"), insert(13, "b")]);
+        record(&mut history, vec![delete(6, "b"), delete(11, "b")]);
+        record(&mut history, vec![delete(5, "a"), delete(9, "a")]);
+        assert_eq!(steps(&mut history), 2);
+    }
+
+    #[test]
+    fn a_different_number_of_cursors_starts_a_new_step() {
+        let mut history = History::default();
+        record(&mut history, vec![insert(5, "a"), insert(11, "a")]);
+        record(&mut history, vec![insert(6, "b")]);
+        assert_eq!(steps(&mut history), 2);
+    }
+
+    #[test]
+    fn undo_reverts_ops_back_to_front_and_redo_replays_them() {
+        let mut history = History::default();
+        let mut rope = Rope::from_str("abc");
+        let ops = vec![insert(1, "x"), delete(3, "c")];
+        for op in &ops {
+            op.apply(&mut rope);
+        }
+        record(&mut history, ops);
+        let (undo, _) = history.undo().unwrap();
+        undo.iter().for_each(|op| op.apply(&mut rope));
+        assert_eq!(rope.to_string(), "abc");
+        let (redo, _) = history.redo().unwrap();
+        redo.iter().for_each(|op| op.apply(&mut rope));
+        assert_eq!(rope.to_string(), "axb");
+        assert!(history.can_undo() && !history.can_redo());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::cli::OpenRequest;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+#[cfg(unix)]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::time::Duration;
+
+// Sent back to a client once its request is open, and once its tabs are closed if it waits
+#[cfg(unix)]
+const OPENED: &str = "opened";
+#[cfg(unix)]
+const CLOSED: &str = "closed";
+
+// How long a client gets to send its request before it is dropped
+#[cfg(unix)]
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long a running instance gets to open a forwarded request before this one opens it itself,
+// e.g. when the running one hangs
+#[cfg(unix)]
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// One socket per user, in the runtime dir when there is one and otherwise in a folder of the temp
+// dir that only this user can get into. None if that folder isn't private, which turns off
+// forwarding rather than talking to whoever made it.
+#[cfg(unix)]
+fn socket_path() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        let user = std::env::var("USER").unwrap_or_default();
+        return Some(PathBuf::from(dir).join(format!("clawpad-{user}.sock")));
+    }
+    let uid = rustix::process::getuid().as_raw();
+    let dir = std::env::temp_dir().join(format!("clawpad-{uid}"));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(_) => return None,
+    }
+    let metadata = std::fs::symlink_metadata(&dir).ok()?;
+    let private = metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o077 == 0;
+    private.then(|| dir.join("instance.sock"))
+}
+
+/// Hands the request to a running instance. Returns false if there is none, so this one has to
+/// open it itself. With `wait`, blocks until the instance reports the tabs closed.
+#[cfg(unix)]
+pub fn forward(request: &OpenRequest) -> bool {
+    socket_path().is_some_and(|path| forward_to(&path, request, REPLY_TIMEOUT))
+}
+
+#[cfg(unix)]
+fn forward_to(path: &Path, request: &OpenRequest, reply_timeout: Duration) -> bool {
+    let Ok(mut stream) = UnixStream::connect(path) else {
+        return false;
+    };
+    let Ok(mut message) = serde_json::to_string(request) else {
+        return false;
+    };
+    message.push('\n');
+    if stream.write_all(message.as_bytes()).is_err() || stream.set_read_timeout(Some(reply_timeout)).is_err() {
+        return false;
+    }
+    let mut replies = BufReader::new(&stream);
+    let mut reply = String::new();
+    if replies.read_line(&mut reply).is_err() || reply.trim_end() != OPENED {
+        return false;
+    }
+    // Tabs stay open for as long as the user likes; also returns when the instance exits
+    if request.wait && stream.set_read_timeout(None).is_ok() {
+        let _ = replies.read_line(&mut reply);
+    }
+    true
+}
+
+#[cfg(not(unix))]
+pub fn forward(_request: &OpenRequest) -> bool {
+    false
+}
+
+/// The invocation a forwarded request came from.
+pub struct Client {
+    #[cfg(unix)]
+    stream: UnixStream,
+}
+
+impl Client {
+    pub fn opened(&mut self) {
+        #[cfg(unix)]
+        let _ = writeln!(self.stream, "{OPENED}");
+    }
+
+    /// Lets a waiting client exit.
+    pub fn closed(mut self) {
+        #[cfg(unix)]
+        let _ = writeln!(self.stream, "{CLOSED}");
+    }
+}
+
+/// Listens for requests from later invocations while this instance runs.
+pub struct Server {
+    requests: Receiver<(OpenRequest, Client)>,
+    #[cfg(unix)]
+    path: PathBuf,
+}
+
+impl Server {
+    /// None if another instance is already listening or the socket can't be created.
+    #[cfg(unix)]
+    pub fn start(ctx: egui::Context) -> Option<Self> {
+        Self::listen(socket_path()?, ctx)
+    }
+
+    #[cfg(unix)]
+    fn listen(path: PathBuf, ctx: egui::Context) -> Option<Self> {
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(error) if error.kind() == io::ErrorKind::AddrInUse => {
+                if UnixStream::connect(&path).is_ok() {
+                    return None;
+                }
+                // Left behind by an instance that crashed
+                std::fs::remove_file(&path).ok()?;
+                UnixListener::bind(&path).ok()?
+            }
+            Err(_) => return None,
+        };
+        let (sender, requests) = std::sync::mpsc::channel();
+        std::thread::Builder::new()
+            .name("instance-server".to_string())
+            .spawn(move || {
+                // Each client is read on its own thread so a slow one doesn't hold up the rest
+                for stream in listener.incoming().flatten() {
+                    let sender = sender.clone();
+                    let ctx = ctx.clone();
+                    std::thread::spawn(move || {
+                        let Some(request) = read_request(&stream) else {
+                            return;
+                        };
+                        if sender.send((request, Client { stream })).is_ok() {
+                            ctx.request_repaint();
+                        }
+                    });
+                }
+            })
+            .ok()?;
+        Some(Self { requests, path })
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(_ctx: egui::Context) -> Option<Self> {
+        None
+    }
+
+    /// Requests received since the last call.
+    pub fn requests(&self) -> Vec<(OpenRequest, Client)> {
+        self.requests.try_iter().collect()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn read_request(stream: &UnixStream) -> Option<OpenRequest> {
+    stream.set_read_timeout(Some(READ_TIMEOUT)).ok()?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::cli::Target;
+
+    fn socket(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("clawpad-instance-test-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn request(wait: bool) -> OpenRequest {
+        let file = Target::File { path: PathBuf::from("/tmp/notes.rs"), line: Some(3), column: None };
+        OpenRequest { targets: vec![file], diff: None, wait }
+    }
+
+    #[test]
+    fn forwarded_requests_reach_the_running_instance() {
+        let path = socket("forward");
+        let server = Server::listen(path.clone(), egui::Context::default()).unwrap();
+        let forwarding = std::thread::spawn(move || forward_to(&path, &request(true), REPLY_TIMEOUT));
+        let (received, mut client) = (0..500)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                server.requests().pop()
+            })
+            .expect("the request never arrived");
+        assert_eq!(serde_json::to_string(&received).unwrap(), serde_json::to_string(&request(true)).unwrap());
+        client.opened();
+        // A waiting client only returns once its tabs are closed
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!forwarding.is_finished());
+        client.closed();
+        assert!(forwarding.join().unwrap());
+    }
+
+    #[test]
+    fn forwarding_gives_up_on_an_instance_that_doesnt_reply() {
+        let path = socket("silent");
+        let _listener = UnixListener::bind(&path).unwrap();
+        assert!(!forward_to(&path, &request(false), Duration::from_millis(100)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn forwarding_without_an_instance_fails() {
+        assert!(!forward_to(&socket("none"), &request(false), REPLY_TIMEOUT));
+    }
+}
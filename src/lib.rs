@@ -0,0 +1,21 @@
+//! The editor's building blocks, shared by the app in `main.rs` and the benchmarks.
+
+pub mod cli;
+pub mod diff;
+pub mod document;
+pub mod editor;
+pub mod encoding;
+pub mod explorer;
+pub mod find;
+pub mod history;
+pub mod instance;
+pub mod line_ending;
+pub mod notifications;
+pub mod save;
+pub mod search;
+pub mod search_panel;
+pub mod session;
+pub mod settings;
+pub mod syntax;
+pub mod walk;
+pub mod watcher;
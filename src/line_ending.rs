@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// How lines end in a file. Documents always hold `\n` and convert when saving.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub const ALL: [Self; 2] = [Self::Lf, Self::CrLf];
+
+    /// What the platform's own tools write.
+    pub fn native() -> Self {
+        if cfg!(windows) { Self::CrLf } else { Self::Lf }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+
+    /// Turns `\n` line breaks into this line ending.
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            Self::Lf => Cow::Borrowed(text),
+            Self::CrLf => Cow::Owned(text.replace('\n', "\r\n")),
+        }
+    }
+}
+
+/// The line endings found in a text.
+#[derive(Debug, PartialEq)]
+pub struct Detected {
+    // The more common one; None without any line breaks
+    pub ending: Option<LineEnding>,
+    pub mixed: bool,
+}
+
+pub fn detect(text: &str) -> Detected {
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    let ending = match (lf, crlf) {
+        (0, 0) => None,
+        _ if crlf > lf => Some(LineEnding::CrLf),
+        _ => Some(LineEnding::Lf),
+    };
+    Detected { ending, mixed: lf > 0 && crlf > 0 }
+}
+
+/// Turns every `\r\n` into `\n`, the form documents are edited in.
+pub fn normalize(text: &str) -> Cow<'_, str> {
+    if text.contains("\r\n") {
+        Cow::Owned(text.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(ending: Option<LineEnding>, mixed: bool) -> Detected {
+        Detected { ending, mixed }
+    }
+
+    #[test]
+    fn detect_without_line_breaks() {
+        assert_eq!(detect(""), detected(None, false));
+        assert_eq!(detect("one line"), detected(None, false));
+        // A lone CR isn't a line break of either kind
+        assert_eq!(detect("a\rb"), detected(None, false));
+    }
+
+    #[test]
+    fn detect_picks_the_more_common_ending() {
+        assert_eq!(detect("a\nb\n"), detected(Some(LineEnding::Lf), false));
+        assert_eq!(detect("a\r\nb\r\n"), detected(Some(LineEnding::CrLf), false));
+        assert_eq!(detect("a\r\nb\r\nc\n"), detected(Some(LineEnding::CrLf), true));
+        // A tie goes to LF
+        assert_eq!(detect("a\r\nb\n"), detected(Some(LineEnding::Lf), true));
+    }
+
+    #[test]
+    fn normalize_only_touches_crlf() {
+        assert!(matches!(normalize("a\nb"), Cow::Borrowed("a\nb")));
+        assert_eq!(normalize("a\r\nb\r\n"), "a\nb\n");
+        assert_eq!(normalize("a\rb\r\r\n"), "a\rb\r\n");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn apply_undoes_normalize() {
+        let text = "a\r\n\r\nb\r\n";
+        assert_eq!(LineEnding::CrLf.apply(&normalize(text)), text);
+        assert_eq!(LineEnding::Lf.apply("a\nb"), "a\nb");
+    }
+}
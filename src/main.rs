@@ -1,19 +1,52 @@
-mod document;
-mod settings;
-mod syntax;
+use clawpad::{
+    cli, diff, document, editor, encoding, explorer, find, instance, line_ending, notifications, search,
+    search_panel, session, settings, syntax, watcher,
+};
 
+use clap::Parser;
+use cli::{Args, OpenRequest, Target};
+use diff::DiffView;
 use document::Document;
+use editor::{EditorOutput, EditorView};
+use encoding::TextEncoding;
+use explorer::{FileTree, TreeEvent};
+use find::FindBar;
+use instance::{Client, Server};
+use line_ending::LineEnding;
+use notifications::Notifications;
+use search::Source;
+use search_panel::SearchPanel;
+use session::{Session, SessionDocument};
 use settings::Settings;
 use syntax::SyntaxHighlighter;
+use watcher::{FileWatcher, Update};
 
 use eframe::egui;
 use std::fs;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 fn main() -> eframe::Result {
     env_logger::init();
-    let settings = Settings::load();
+    let args = Args::parse();
+    let request = args.request();
+    // Folders make a workspace of their own, so they get a new instance
+    if !args.new_instance && !request.has_folders() && instance::forward(&request) {
+        return Ok(());
+    }
+    // A --wait window closes itself, taking anything forwarded to it along
+    let serve = !args.new_instance && !args.wait;
+    let roots = if request.has_folders() { request.folders() } else { vec![PathBuf::from(".")] };
+    let (settings, settings_error) = match Settings::load() {
+        Ok(settings) => (settings, None),
+        Err(error) => {
+            let message = format!(
+                "Couldn't load {}: {error}. Using the default settings; changes to them won't be saved.",
+                Settings::path().display()
+            );
+            (Settings::default(), Some(message))
+        }
+    };
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1400.0, 900.0])
@@ -30,11 +63,31 @@ fn main() -> eframe::Result {
             } else {
                 cc.egui_ctx.set_visuals(egui::Visuals::light());
             }
-            Ok(Box::new(ClawpadApp::new(cc, settings)))
+            let mut app = ClawpadApp::new(cc, settings, roots);
+            if let Some(message) = settings_error {
+                app.notifications.error(message);
+                app.settings_load_failed = true;
+            }
+            // A --wait window is only there for the files it was given
+            if !request.wait {
+                app.restore_session();
+                app.keep_session = true;
+            }
+            if serve {
+                app.server = Server::start(cc.egui_ctx.clone());
+            }
+            app.waiting = app.open_request(request);
+            Ok(Box::new(app))
         }),
     )
 }
 
+// A close waiting for the user to decide what happens to unsaved changes
+enum PendingClose {
+    Tab(u64),
+    Exit,
+}
+
 struct ClawpadApp {
     documents: Vec<Document>,
     active_index: usize,
@@ -46,27 +99,45 @@ struct ClawpadApp {
     show_preview: bool,
     show_minimap: bool,
     distraction_free: bool,
-    sidebar_files: Vec<PathBuf>,
+    // One per workspace folder; the first is the workspace root
+    file_trees: Vec<FileTree>,
+    watcher: FileWatcher,
+    // Last title sent to the window
+    window_title: String,
+    // Tabs opened with --wait; the app exits once they are all closed
+    waiting: Vec<u64>,
+    // Receives files from later invocations; None if another instance already does
+    server: Option<Server>,
+    // Forwarded --wait requests, told once their tabs are all closed
+    waiting_clients: Vec<(Vec<u64>, Client)>,
+    // Whether the tabs are saved on exit for the next launch in this workspace
+    keep_session: bool,
+    // Settings changed in the app aren't written over a settings file that couldn't be loaded
+    settings_load_failed: bool,
+    pending_close: Option<PendingClose>,
+    // Set once the user has dealt with unsaved changes, so the next close request goes through
+    exiting: bool,
+    diffs: Vec<DiffView>,
+    // Diff between the active document and its changed file: (document id, diff)
+    disk_diff: Option<(u64, String)>,
     
+    notifications: Notifications,
+
+    // In-file find/replace
+    find: FindBar,
+
     // Search State
     show_search: bool,
-    search_query: String,
-    search_results: Vec<SearchResult>,
+    search_panel: SearchPanel,
     
     // Markdown Preview Cache
     md_cache: egui_commonmark::CommonMarkCache,
 }
 
-struct SearchResult {
-    path: PathBuf,
-    line_number: usize,
-    line_content: String,
-}
-
 impl ClawpadApp {
-    fn new(_cc: &eframe::CreationContext<'_>, settings: Settings) -> Self {
+    fn new(_cc: &eframe::CreationContext<'_>, settings: Settings, roots: Vec<PathBuf>) -> Self {
         let mut app = Self {
-            documents: vec![Document::untitled()],
+            documents: Vec::new(),
             active_index: 0,
             settings,
             highlighter: SyntaxHighlighter::new(),
@@ -74,33 +145,313 @@ impl ClawpadApp {
             show_preview: false,
             show_minimap: true,
             distraction_free: false,
-            sidebar_files: Vec::new(),
+            file_trees: Vec::new(),
+            watcher: FileWatcher::new(),
+            window_title: String::new(),
+            waiting: Vec::new(),
+            server: None,
+            waiting_clients: Vec::new(),
+            keep_session: false,
+            settings_load_failed: false,
+            pending_close: None,
+            exiting: false,
+            diffs: Vec::new(),
+            disk_diff: None,
+            notifications: Notifications::default(),
+            find: FindBar::default(),
             show_search: false,
-            search_query: String::new(),
-            search_results: Vec::new(),
+            search_panel: SearchPanel::default(),
             md_cache: egui_commonmark::CommonMarkCache::default(),
         };
-        app.refresh_sidebar();
+        app.documents.push(app.new_document(String::new(), None));
+        app.set_workspace_roots(roots);
         app
     }
 
-    fn refresh_sidebar(&mut self) {
-        let mut files = Vec::new();
-        for entry in WalkDir::new(".").max_depth(2).into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.starts_with('.') || name == "settings.json" {
+    // Like `Document::new`, but text without line breaks gets the configured line ending
+    fn new_document(&self, content: String, path: Option<PathBuf>) -> Document {
+        let has_line_breaks = content.contains('\n');
+        let mut doc = Document::new(content, path);
+        if !has_line_breaks {
+            doc.line_ending = self.settings.default_line_ending;
+        }
+        doc
+    }
+
+    fn warn_mixed_line_endings(&mut self, doc: &Document) {
+        if doc.mixed_line_endings {
+            self.notifications.warning(format!(
+                "{} has mixed line endings; saving turns them all into {}.",
+                doc.name(),
+                doc.line_ending.label()
+            ));
+        }
+    }
+
+    /// Reopens the tabs and panels this workspace had when the app last exited.
+    fn restore_session(&mut self) {
+        let roots = self.workspace_roots();
+        let session = match Session::load(&roots) {
+            Ok(Some(session)) => session,
+            Ok(None) => return,
+            Err(error) => {
+                let path = Session::path(&roots).unwrap_or_default();
+                self.notifications.warning(format!("Couldn't restore the last session from {}: {error}", path.display()));
+                return;
+            }
+        };
+        let documents: Vec<Document> =
+            session.documents.into_iter().filter_map(|saved| self.restore_document(saved)).collect();
+        let unsaved = documents.iter().filter(|doc| doc.is_dirty).count();
+        if unsaved > 0 {
+            self.notifications.info(format!("Restored unsaved changes in {unsaved} tab(s) from the last session."));
+        }
+        if !documents.is_empty() {
+            self.active_index = session.active_index.min(documents.len() - 1);
+            self.documents = documents;
+        }
+        self.show_sidebar = session.show_sidebar;
+        self.show_preview = session.show_preview;
+        self.show_minimap = session.show_minimap;
+        self.show_search = session.show_search;
+    }
+
+    // None if the file is gone and there were no unsaved changes to keep
+    fn restore_document(&mut self, saved: SessionDocument) -> Option<Document> {
+        let known_encoding = saved.encoding.as_deref().and_then(TextEncoding::from_label);
+        let mut doc = match saved.file_path {
+            Some(path) => match known_encoding.map_or_else(|| encoding::read(&path), |known| encoding::read_as(&path, known)) {
+                Ok(decoded) => {
+                    self.watcher.watch_file(&path);
+                    let mut doc = self.new_document(decoded.text, Some(path));
+                    doc.encoding = decoded.encoding;
+                    note_invalid_bytes(&mut doc, decoded.had_errors, &mut self.notifications);
+                    // Switched from the status bar without saving
+                    if let Some(ending) = saved.line_ending
+                        && ending != doc.line_ending
+                        && saved.text.is_some()
+                    {
+                        doc.set_line_ending(ending);
+                    }
+                    // One undo step away from what is on disk
+                    if let Some(text) = saved.text
+                        && text != doc.text()
+                    {
+                        doc.replace_text(&text);
+                    }
+                    doc
+                }
+                Err(error) => {
+                    if path.exists() {
+                        self.notifications.warning(format!("Couldn't reopen {}: {error}", path.display()));
+                    }
+                    let mut doc = Document::new(saved.text?, Some(path));
+                    doc.encoding = known_encoding.unwrap_or(TextEncoding::UTF8);
+                    doc.line_ending = saved.line_ending.unwrap_or(self.settings.default_line_ending);
+                    doc.mark_unsaved();
+                    doc
+                }
+            },
+            None => {
+                let text = saved.text.unwrap_or_default();
+                let mut doc = Document::new(text, None);
+                doc.line_ending = saved.line_ending.unwrap_or(self.settings.default_line_ending);
+                if doc.rope.len_chars() > 0 {
+                    doc.mark_unsaved();
+                }
+                doc
+            }
+        };
+        if !saved.cursors.is_empty() {
+            doc.set_cursors(saved.cursors, saved.primary);
+        }
+        doc.scroll_offset = egui::Vec2::from(saved.scroll_offset);
+        Some(doc)
+    }
+
+    fn session(&mut self) -> Session {
+        let hot_exit = self.settings.hot_exit;
+        let documents = self
+            .documents
+            .iter_mut()
+            .map(|doc| SessionDocument {
+                text: (hot_exit && (doc.is_dirty || doc.file_path.is_none())).then(|| doc.text().to_string()),
+                encoding: Some(doc.encoding.label()),
+                line_ending: Some(doc.line_ending),
+                file_path: doc.file_path.clone(),
+                cursors: doc.cursors.clone(),
+                primary: doc.primary,
+                scroll_offset: doc.scroll_offset.into(),
+            })
+            .collect();
+        Session {
+            documents,
+            active_index: self.active_index,
+            show_sidebar: self.show_sidebar,
+            show_preview: self.show_preview,
+            show_minimap: self.show_minimap,
+            show_search: self.show_search,
+        }
+    }
+
+    /// Opens what was given on the command line, here or in a later invocation. Returns the
+    /// tabs to wait for if the request asked to.
+    fn open_request(&mut self, request: OpenRequest) -> Vec<u64> {
+        let mut folders = Vec::new();
+        let mut opened = Vec::new();
+        for target in request.targets {
+            match target {
+                Target::Folder(path) => folders.push(path),
+                Target::File { path, line, column } => {
+                    // A missing file opens empty and is created on save
+                    if !path.exists() {
+                        let doc = self.new_document(String::new(), Some(path));
+                        self.documents.push(doc);
+                        self.active_index = self.documents.len() - 1;
+                    } else if !self.open_file(path) {
                         continue;
                     }
+                    if let Some(line) = line {
+                        self.active_doc_mut().go_to(line - 1, column.map_or(0, |column| column - 1));
+                    }
+                    opened.push(self.active_doc().id);
                 }
-                if path.to_string_lossy().contains("target") {
-                    continue;
+                Target::Stdin(text) => {
+                    // Unsaved, like anything typed into an untitled tab
+                    let mut doc = self.new_document(text, None);
+                    doc.mark_unsaved();
+                    opened.push(doc.id);
+                    self.documents.push(doc);
+                    self.active_index = self.documents.len() - 1;
                 }
-                files.push(path.to_path_buf());
             }
         }
-        self.sidebar_files = files;
+        if !folders.is_empty() && folders != self.workspace_roots() {
+            self.set_workspace_roots(folders);
+        }
+        if let Some(paths) = request.diff {
+            self.diffs.push(DiffView::files(&paths[0], &paths[1]));
+        }
+
+        // The blank tab the app starts with isn't needed next to opened files
+        let first = &self.documents[0];
+        if !opened.is_empty() && first.file_path.is_none() && first.rope.len_chars() == 0 && !opened.contains(&first.id) {
+            self.documents.remove(0);
+            self.active_index = self.active_index.saturating_sub(1);
+        }
+        if request.wait { opened } else { Vec::new() }
+    }
+
+    // Opens files sent by later invocations and brings the window to the front
+    fn handle_forwarded(&mut self, ctx: &egui::Context) {
+        let Some(server) = &self.server else {
+            return;
+        };
+        let requests = server.requests();
+        if requests.is_empty() {
+            return;
+        }
+        for (request, mut client) in requests {
+            let waiting = self.open_request(request);
+            client.opened();
+            if !waiting.is_empty() {
+                self.waiting_clients.push((waiting, client));
+            }
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    }
+
+    /// Closes the tab, asking first if it has unsaved changes.
+    fn request_close_tab(&mut self, index: usize) {
+        let doc = &self.documents[index];
+        if doc.is_dirty {
+            self.pending_close = Some(PendingClose::Tab(doc.id));
+        } else {
+            self.close_tab(index);
+        }
+    }
+
+    // Unsaved changes survive exiting when the session keeps them
+    fn changes_kept(&self) -> bool {
+        self.keep_session && self.settings.hot_exit
+    }
+
+    fn close_tab(&mut self, index: usize) {
+        self.documents.remove(index);
+        if self.documents.is_empty() {
+            let doc = self.new_document(String::new(), None);
+            self.documents.push(doc);
+        }
+        if self.active_index >= self.documents.len() {
+            self.active_index = self.documents.len() - 1;
+        }
+    }
+
+    fn workspace_root(&self) -> Option<&Path> {
+        self.file_trees.first().map(FileTree::root)
+    }
+
+    fn workspace_roots(&self) -> Vec<PathBuf> {
+        self.file_trees.iter().map(|tree| tree.root().to_path_buf()).collect()
+    }
+
+    /// Makes these folders the workspace, keeping the trees of folders that stay.
+    fn set_workspace_roots(&mut self, roots: Vec<PathBuf>) {
+        let roots: Vec<PathBuf> = roots.iter().map(|root| absolute(root)).collect();
+        let mut trees = std::mem::take(&mut self.file_trees);
+        self.file_trees = roots
+            .iter()
+            .map(|root| match trees.iter().position(|tree| tree.root() == root) {
+                Some(index) => trees.swap_remove(index),
+                None => FileTree::new(root.clone(), &self.settings),
+            })
+            .collect();
+        self.watcher.set_roots(&roots, &self.settings.exclude_patterns);
+        self.search_panel.set_roots(roots);
+        self.search_panel.search(&self.settings, &self.documents);
+    }
+
+    fn add_workspace_folder(&mut self, folder: PathBuf) {
+        let folder = absolute(&folder);
+        let mut roots = self.workspace_roots();
+        if roots.iter().any(|root| folder.starts_with(root)) {
+            return;
+        }
+        // Folders inside the new one would be listed and searched twice
+        roots.retain(|root| !root.starts_with(&folder));
+        roots.push(folder);
+        self.set_workspace_roots(roots);
+    }
+
+    fn remove_workspace_folder(&mut self, folder: &Path) {
+        let roots = self.workspace_roots().into_iter().filter(|root| root != folder).collect();
+        self.set_workspace_roots(roots);
+    }
+
+    fn refresh_trees(&mut self) {
+        for tree in &mut self.file_trees {
+            tree.refresh();
+        }
+    }
+
+    fn title(&self) -> String {
+        let names: Vec<String> = self.file_trees.iter().map(FileTree::name).collect();
+        if names.is_empty() {
+            "Clawpad Professional".to_string()
+        } else {
+            format!("{} — Clawpad Professional", names.join(", "))
+        }
+    }
+
+    fn reveal_active_file(&mut self) {
+        self.show_sidebar = true;
+        if let Some(path) = self.documents[self.active_index].file_path.clone() {
+            for tree in &mut self.file_trees {
+                tree.reveal(&path, &mut self.settings);
+            }
+            self.save_settings();
+        }
     }
 
     fn active_doc(&self) -> &Document {
@@ -111,51 +462,252 @@ impl ClawpadApp {
         &mut self.documents[self.active_index]
     }
 
-    fn open_file(&mut self, path: PathBuf) {
+    // Returns whether the file is now the active document
+    fn open_file(&mut self, path: PathBuf) -> bool {
         // Check if already open
         if let Some(index) = self.documents.iter().position(|d| d.file_path.as_ref() == Some(&path)) {
             self.active_index = index;
-            return;
+            return true;
         }
 
-        if let Ok(content) = fs::read_to_string(&path) {
-            let doc = Document::new(content, Some(path));
-            self.documents.push(doc);
-            self.active_index = self.documents.len() - 1;
+        match encoding::read(&path) {
+            Ok(decoded) => {
+                self.watcher.watch_file(&path);
+                let mut doc = self.new_document(decoded.text, Some(path));
+                doc.encoding = decoded.encoding;
+                note_invalid_bytes(&mut doc, decoded.had_errors, &mut self.notifications);
+                self.warn_mixed_line_endings(&doc);
+                self.documents.push(doc);
+                self.active_index = self.documents.len() - 1;
+                true
+            }
+            Err(error) => {
+                self.notifications.error(format!("Couldn't open {}: {error}", path.display()));
+                false
+            }
+        }
+    }
+
+    // Keeps open documents pointing at files moved or deleted from the explorer
+    fn apply_tree_event(&mut self, event: TreeEvent) {
+        match event {
+            TreeEvent::Open(path) => {
+                self.open_file(path);
+            }
+            TreeEvent::RemoveRoot(root) => self.remove_workspace_folder(&root),
+            TreeEvent::ExpandedChanged => self.save_settings(),
+            TreeEvent::Renamed { from, to } => {
+                let from = absolute(&from);
+                for doc in &mut self.documents {
+                    let moved = doc.file_path.as_deref().and_then(|path| {
+                        let rest = absolute(path).strip_prefix(&from).ok()?.to_path_buf();
+                        Some(if rest.as_os_str().is_empty() { to.clone() } else { to.join(rest) })
+                    });
+                    if let Some(path) = moved {
+                        doc.set_file_path(path);
+                    }
+                }
+            }
+            TreeEvent::Deleted(path) => {
+                // The buffer stays open so its contents can be saved again
+                let path = absolute(&path);
+                for doc in &mut self.documents {
+                    if doc.file_path.as_deref().is_some_and(|file| absolute(file).starts_with(&path)) {
+                        doc.mark_unsaved();
+                    }
+                }
+            }
+        }
+    }
+
+    // Follows changes made to files by other programs
+    fn apply_disk_changes(&mut self) {
+        for update in self.watcher.updates() {
+            match update {
+                Update::Changed(changes) => {
+                    for tree in &mut self.file_trees {
+                        for path in &changes {
+                            tree.changed(path);
+                        }
+                    }
+                    // Read on the watcher thread, coming back as an Update::Read
+                    for doc in &self.documents {
+                        if let Some(path) = &doc.file_path
+                            && changes.contains(&absolute(path))
+                        {
+                            self.watcher.read(path, doc.encoding);
+                        }
+                    }
+                }
+                Update::Read { path, encoding, result } => {
+                    // Closed or reopened with another encoding in the meantime
+                    let Some(doc) = self.documents.iter_mut()
+                        .find(|doc| doc.file_path.as_deref() == Some(path.as_path()) && doc.encoding == encoding)
+                    else {
+                        continue;
+                    };
+                    match result {
+                        Ok(disk) if line_ending::normalize(&disk.text) == doc.text() => doc.mark_on_disk(&disk.text),
+                        // Nothing new, e.g. our own save followed by more typing
+                        Ok(disk) if doc.is_on_disk(&disk.text) => {}
+                        Ok(disk) if !doc.is_dirty => {
+                            doc.reload(&disk.text);
+                            note_invalid_bytes(doc, disk.had_errors, &mut self.notifications);
+                        }
+                        Ok(_) => {
+                            doc.disk_changed = true;
+                            doc.mark_unsaved();
+                        }
+                        // Deleted: keep the text so it can be saved again
+                        Err(_) if !path.exists() => doc.mark_unsaved(),
+                        Err(error) => self.notifications.warning(format!("Couldn't reload {}: {error}", path.display())),
+                    }
+                }
+            }
+        }
+    }
+
+    // Opens a search result and selects the match, which scrolls the editor to it
+    fn open_match(&mut self, source: Source, chars: Range<usize>) {
+        let opened = match source {
+            Source::File(path) => self.open_file(path),
+            Source::Untitled(id) => match self.documents.iter().position(|doc| doc.id == id) {
+                Some(index) => {
+                    self.active_index = index;
+                    true
+                }
+                None => false,
+            },
+        };
+        if opened {
+            let doc = self.active_doc_mut();
+            let len = doc.rope.len_chars();
+            doc.set_selection((chars.start.min(len), chars.end.min(len)));
         }
     }
 
-    fn save_current(&mut self) {
+    // Returns whether the document was saved
+    fn save_current(&mut self) -> bool {
+        let backup = self.settings.backup_on_save;
         let doc = self.active_doc_mut();
-        if let Some(path) = &doc.file_path {
-            if fs::write(path, &doc.content_cache).is_ok() {
-                doc.is_dirty = false;
+        if let Some(path) = doc.file_path.clone() {
+            // Only once, so saving again goes ahead
+            if doc.invalid_bytes {
+                doc.invalid_bytes = false;
+                let message = format!(
+                    "Didn't save {}: its invalid bytes would be replaced with �. Save again to do that anyway, or use Reopen with Encoding.",
+                    doc.name()
+                );
+                self.notifications.warning(message);
+                return false;
+            }
+            match doc.save_to(&path, backup) {
+                Ok(()) => {
+                    doc.is_dirty = false;
+                    true
+                }
+                Err(error) => {
+                    self.notifications.error(format!("Couldn't save {}: {error}", path.display()));
+                    false
+                }
             }
         } else {
-            self.save_current_as();
+            self.save_current_as()
         }
     }
 
-    fn save_current_as(&mut self) {
-        if let Some(path) = rfd::FileDialog::new().save_file() {
-            let doc = self.active_doc_mut();
-            if fs::write(&path, &doc.content_cache).is_ok() {
-                doc.file_path = Some(path.clone());
+    fn save_current_as(&mut self) -> bool {
+        let Some(path) = rfd::FileDialog::new().save_file() else {
+            return false;
+        };
+        let backup = self.settings.backup_on_save;
+        let doc = self.active_doc_mut();
+        match doc.save_to(&path, backup) {
+            Ok(()) => {
+                self.watcher.watch_file(&path);
+                let doc = self.active_doc_mut();
+                doc.set_file_path(path);
                 doc.is_dirty = false;
-                // Update language
-                doc.language = match path.extension().and_then(|e| e.to_str()) {
-                    Some("rs") => "Rust",
-                    Some("py") => "Python",
-                    Some("md") => "Markdown",
-                    _ => "Plain Text",
-                }.to_string();
-                self.refresh_sidebar();
+                self.refresh_trees();
+                true
+            }
+            Err(error) => {
+                self.notifications.error(format!("Couldn't save {}: {error}", path.display()));
+                false
+            }
+        }
+    }
+
+    // Decodes the file again, e.g. when the detected encoding was wrong; undo brings back the old text
+    fn reopen_with_encoding(&mut self, encoding: TextEncoding) {
+        let doc = &mut self.documents[self.active_index];
+        let Some(path) = doc.file_path.clone() else {
+            return;
+        };
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let (content, had_errors) = encoding.decode(&bytes);
+                doc.encoding = encoding;
+                doc.reload(&content);
+                note_invalid_bytes(doc, had_errors, &mut self.notifications);
+            }
+            Err(error) => self.notifications.error(format!("Couldn't reopen {}: {error}", path.display())),
+        }
+    }
+
+    fn save_with_encoding(&mut self, encoding: TextEncoding) {
+        let previous = std::mem::replace(&mut self.active_doc_mut().encoding, encoding);
+        // The text may not fit the new encoding, or Save As was cancelled
+        if !self.save_current() {
+            self.active_doc_mut().encoding = previous;
+        }
+    }
+
+    fn encoding_menu(&mut self, ui: &mut egui::Ui) {
+        let current = self.active_doc().encoding;
+        let has_file = self.active_doc().file_path.is_some();
+        ui.add_enabled_ui(has_file, |ui| {
+            ui.menu_button("Reopen with Encoding", |ui| {
+                for encoding in TextEncoding::all() {
+                    if ui.selectable_label(encoding == current, encoding.label()).clicked() {
+                        self.reopen_with_encoding(encoding);
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+        ui.menu_button("Save with Encoding", |ui| {
+            for encoding in TextEncoding::all() {
+                if ui.selectable_label(encoding == current, encoding.label()).clicked() {
+                    self.save_with_encoding(encoding);
+                    ui.close_menu();
+                }
             }
+        });
+    }
+
+    fn save_settings(&mut self) {
+        // Leaves a file that failed to load for the user to fix rather than replacing it with defaults
+        if self.settings_load_failed {
+            return;
+        }
+        if let Err(error) = self.settings.save() {
+            self.notifications.error(format!("Couldn't save settings to {}: {error}", Settings::path().display()));
         }
     }
 }
 
 impl eframe::App for ClawpadApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Too late to show anything, so this only goes to the log; a failure that would lose
+        // changes was already caught when the close was requested
+        if self.keep_session
+            && let Err(error) = self.session().save(&self.workspace_roots())
+        {
+            log::error!("Couldn't save the session: {error}");
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Force redraw to handle real-time transparency/settings changes
         ctx.request_repaint();
@@ -175,6 +727,82 @@ impl eframe::App for ClawpadApp {
         
         ctx.set_visuals(visuals);
 
+        // Only taken while the editor has focus; text fields keep their own undo
+        let editor_focused = ctx.memory(|m| m.has_focus(EditorView::id_for(self.active_doc())));
+        let (undo, redo) = if editor_focused {
+            ctx.input_mut(|i| {
+                let redo = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)
+                    || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+                let undo = i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z);
+                (undo, redo)
+            })
+        } else {
+            (false, false)
+        };
+        if undo {
+            self.active_doc_mut().undo();
+        }
+        if redo {
+            self.active_doc_mut().redo();
+        }
+
+        // Ctrl+Shift+F is checked first since Ctrl+F would also match it
+        let (global_search, find, replace, find_next, find_previous) = ctx.input_mut(|i| {
+            let global_search = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::F);
+            let find = i.consume_key(egui::Modifiers::COMMAND, egui::Key::F);
+            let replace = i.consume_key(egui::Modifiers::COMMAND, egui::Key::H);
+            let find_previous = i.consume_key(egui::Modifiers::SHIFT, egui::Key::F3);
+            let find_next = i.consume_key(egui::Modifiers::NONE, egui::Key::F3);
+            (global_search, find, replace, find_next, find_previous)
+        });
+        let (result_next, result_previous) = ctx.input_mut(|i| {
+            let result_previous = i.consume_key(egui::Modifiers::SHIFT, egui::Key::F4);
+            let result_next = i.consume_key(egui::Modifiers::NONE, egui::Key::F4);
+            (result_next, result_previous)
+        });
+        if (result_next || result_previous)
+            && let Some((source, chars)) = self.search_panel.step(result_next)
+        {
+            self.open_match(source, chars);
+        }
+        if global_search {
+            self.show_search = !self.show_search;
+        }
+        if find || replace {
+            let doc = &self.documents[self.active_index];
+            self.find.open(doc, replace);
+        }
+        if find_next {
+            self.find.find_next(&mut self.documents[self.active_index]);
+        }
+        if find_previous {
+            self.find.find_previous(&mut self.documents[self.active_index]);
+        }
+
+        if ctx.input(|i| i.viewport().close_requested()) && !self.exiting {
+            // Written now, while unsaved changes can still be offered for saving if it fails
+            if self.changes_kept()
+                && let Err(error) = self.session().save(&self.workspace_roots())
+            {
+                self.notifications.error(format!("Couldn't save the session, so unsaved changes won't be kept: {error}"));
+                self.keep_session = false;
+            }
+            if !self.changes_kept() && self.documents.iter().any(|doc| doc.is_dirty) {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.pending_close = Some(PendingClose::Exit);
+            }
+        }
+
+        self.search_panel.poll();
+        self.apply_disk_changes();
+        self.handle_forwarded(ctx);
+
+        let title = self.title();
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+
         if !self.distraction_free {
             self.draw_top_panel(ctx);
             self.draw_status_bar(ctx);
@@ -184,14 +812,31 @@ impl eframe::App for ClawpadApp {
             }
         }
         self.draw_central_panel(ctx);
+        for diff in &mut self.diffs {
+            diff.show(ctx);
+        }
+        self.diffs.retain(|diff| diff.open);
+        self.draw_close_dialog(ctx);
+        self.notifications.show(ctx);
+
+        // With --wait, whoever launched us continues once the tabs are closed
+        if !self.waiting.is_empty() && !self.documents.iter().any(|doc| self.waiting.contains(&doc.id)) {
+            self.waiting.clear();
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+        let documents = &self.documents;
+        let (done, waiting) = std::mem::take(&mut self.waiting_clients)
+            .into_iter()
+            .partition(|(ids, _)| !documents.iter().any(|doc| ids.contains(&doc.id)));
+        self.waiting_clients = waiting;
+        for (_, client) in done {
+            client.closed();
+        }
 
         // Shortcuts
         if ctx.input(|i| i.key_pressed(egui::Key::F11)) {
             self.distraction_free = !self.distraction_free;
         }
-        if ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::F)) {
-            self.show_search = !self.show_search;
-        }
     }
 }
 
@@ -201,7 +846,8 @@ impl ClawpadApp {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     if ui.button("New Tab").clicked() {
-                        self.documents.push(Document::untitled());
+                        let doc = self.new_document(String::new(), None);
+                        self.documents.push(doc);
                         self.active_index = self.documents.len() - 1;
                         ui.close_menu();
                     }
@@ -211,21 +857,61 @@ impl ClawpadApp {
                         }
                         ui.close_menu();
                     }
+                    if ui.button("Open Folder...").clicked() {
+                        if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                            self.set_workspace_roots(vec![folder]);
+                        }
+                        ui.close_menu();
+                    }
+                    if ui.button("Add Folder to Workspace...").clicked() {
+                        let mut dialog = rfd::FileDialog::new();
+                        if let Some(root) = self.workspace_root() {
+                            dialog = dialog.set_directory(root);
+                        }
+                        if let Some(folder) = dialog.pick_folder() {
+                            self.add_workspace_folder(folder);
+                        }
+                        ui.close_menu();
+                    }
                     if ui.button("Save").clicked() {
                         self.save_current();
                         ui.close_menu();
                     }
+                    self.encoding_menu(ui);
                     ui.separator();
                     if ui.button("Exit").clicked() {
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
 
+                ui.menu_button("Edit", |ui| {
+                    let can_undo = self.active_doc().history.can_undo();
+                    let can_redo = self.active_doc().history.can_redo();
+                    if ui.add_enabled(can_undo, egui::Button::new("Undo").shortcut_text("Ctrl+Z")).clicked() {
+                        self.active_doc_mut().undo();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(can_redo, egui::Button::new("Redo").shortcut_text("Ctrl+Shift+Z")).clicked() {
+                        self.active_doc_mut().redo();
+                        ui.close_menu();
+                    }
+                });
+
                 ui.menu_button("View", |ui| {
                     ui.checkbox(&mut self.show_sidebar, "Show Sidebar");
+                    if ui.button("Reveal Active File").clicked() {
+                        self.reveal_active_file();
+                        ui.close_menu();
+                    }
                     ui.checkbox(&mut self.show_preview, "Show Markdown Preview");
                     ui.checkbox(&mut self.show_minimap, "Show Minimap");
-                    ui.checkbox(&mut self.show_search, "Show Search Panel");
+                    ui.checkbox(&mut self.show_search, "Show Search Panel (Ctrl+Shift+F)");
+                    ui.checkbox(&mut self.notifications.show_history, "Show Notifications");
+                    if ui.checkbox(&mut self.settings.show_ignored, "Show Ignored Files").changed() {
+                        self.save_settings();
+                        self.refresh_trees();
+                        self.search_panel.search(&self.settings, &self.documents);
+                    }
                     if ui.checkbox(&mut self.distraction_free, "Distraction-free Mode (F11)").clicked() {
                         ui.close_menu();
                     }
@@ -233,10 +919,10 @@ impl ClawpadApp {
 
                 ui.menu_button("Settings", |ui| {
                     if ui.add(egui::Slider::new(&mut self.settings.font_size, 8.0..=32.0).text("Font Size")).changed() {
-                        let _ = self.settings.save();
+                        self.save_settings();
                     }
                     if ui.add(egui::Slider::new(&mut self.settings.transparency, 0.1..=1.0).text("Transparency")).changed() {
-                        let _ = self.settings.save();
+                        self.save_settings();
                     }
                     if ui.checkbox(&mut self.settings.theme_dark, "Dark Theme").changed() {
                         if self.settings.theme_dark {
@@ -244,7 +930,20 @@ impl ClawpadApp {
                         } else {
                             ctx.set_visuals(egui::Visuals::light());
                         }
-                        let _ = self.settings.save();
+                        self.save_settings();
+                    }
+                    ui.separator();
+                    ui.label("Exclude (one gitignore pattern per line)");
+                    let mut patterns = self.settings.exclude_patterns.join("\n");
+                    let response = ui.add(egui::TextEdit::multiline(&mut patterns).desired_rows(3));
+                    if response.changed() {
+                        self.settings.exclude_patterns = patterns.split('\n').map(str::to_string).collect();
+                        self.save_settings();
+                    }
+                    if response.lost_focus() {
+                        self.watcher.set_roots(&self.workspace_roots(), &self.settings.exclude_patterns);
+                        self.refresh_trees();
+                        self.search_panel.search(&self.settings, &self.documents);
                     }
                 });
             });
@@ -265,21 +964,91 @@ impl ClawpadApp {
                     if response.clicked() {
                         self.active_index = i;
                     }
-                    if response.middle_clicked() {
+                    let close = ui.small_button("×").on_hover_text("Close");
+                    if response.middle_clicked() || close.clicked() {
                         close_tab = Some(i);
                     }
                 }
 
                 if let Some(i) = close_tab {
-                    if self.documents.len() > 1 {
-                        self.documents.remove(i);
-                        if self.active_index >= self.documents.len() {
-                            self.active_index = self.documents.len().saturating_sub(1);
-                        }
-                    }
+                    self.request_close_tab(i);
+                }
+            });
+        });
+    }
+
+    // Asks whether to save before a dirty tab is closed or the app exits
+    fn draw_close_dialog(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.pending_close else {
+            return;
+        };
+        let dirty: Vec<usize> = match pending {
+            PendingClose::Tab(id) => self.documents.iter().position(|doc| doc.id == *id).into_iter().collect(),
+            PendingClose::Exit => (0..self.documents.len()).filter(|&i| self.documents[i].is_dirty).collect(),
+        };
+        if dirty.is_empty() {
+            self.pending_close = None;
+            return;
+        }
+        let exit = matches!(pending, PendingClose::Exit);
+        let message = if exit && dirty.len() > 1 {
+            format!("{} files have unsaved changes.", dirty.len())
+        } else {
+            format!("Do you want to save the changes you made to {}?", self.documents[dirty[0]].name())
+        };
+
+        let (mut save, mut discard, mut cancel) = (false, false, false);
+        let response = egui::Modal::new(egui::Id::new("unsaved_changes")).show(ctx, |ui| {
+            ui.set_width(340.0);
+            ui.heading("Unsaved Changes");
+            ui.label(message);
+            if dirty.len() > 1 {
+                for &i in &dirty {
+                    ui.label(format!("  • {}", self.documents[i].name()));
                 }
+            }
+            ui.label("Your changes will be lost if you don't save them.");
+            ui.separator();
+            ui.horizontal(|ui| {
+                save = ui.button(if dirty.len() > 1 { "Save All" } else { "Save" }).clicked();
+                discard = ui.button("Don't Save").clicked();
+                cancel = ui.button("Cancel").clicked();
             });
         });
+        if response.should_close() && !save && !discard {
+            cancel = true;
+        }
+        if save {
+            let ids: Vec<u64> = dirty.iter().map(|&i| self.documents[i].id).collect();
+            // Saving works on the active tab, so each one takes a turn
+            let active = self.active_index;
+            for &i in &dirty {
+                self.active_index = i;
+                self.save_current();
+            }
+            self.active_index = active;
+            // A cancelled Save As or a failed write keeps everything open
+            cancel = self.documents.iter().any(|doc| doc.is_dirty && ids.contains(&doc.id));
+        }
+        if cancel {
+            self.pending_close = None;
+            return;
+        }
+        if !save && !discard {
+            return;
+        }
+        match self.pending_close.take() {
+            Some(PendingClose::Tab(id)) => {
+                if let Some(index) = self.documents.iter().position(|doc| doc.id == id) {
+                    self.close_tab(index);
+                }
+            }
+            Some(PendingClose::Exit) => {
+                self.exiting = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            None => {}
+        }
     }
 
     fn draw_status_bar(&mut self, ctx: &egui::Context) {
@@ -291,8 +1060,34 @@ impl ClawpadApp {
                 ui.label(format!("Lines: {}", doc.rope.len_lines()));
                 ui.separator();
                 ui.label(format!("Chars: {}", doc.rope.len_chars()));
+                ui.separator();
+                let encoding = doc.encoding.label();
+                ui.menu_button(encoding, |ui| self.encoding_menu(ui)).response.on_hover_text("Encoding");
+                ui.separator();
+                let doc = self.active_doc_mut();
+                let current = doc.line_ending;
+                ui.menu_button(current.label(), |ui| {
+                    for ending in LineEnding::ALL {
+                        if ui.selectable_label(ending == current, ending.label()).clicked() {
+                            doc.set_line_ending(ending);
+                            ui.close_menu();
+                        }
+                    }
+                })
+                .response
+                .on_hover_text("Line endings");
+                if doc.mixed_line_endings {
+                    ui.colored_label(ui.visuals().warn_fg_color, "⚠ Mixed")
+                        .on_hover_text(format!("This file has both LF and CRLF line breaks; saving turns them all into {}", current.label()));
+                }
                 
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let unread = self.notifications.unread();
+                    let bell = if unread > 0 { format!("🔔 {unread}") } else { "🔔".to_string() };
+                    if ui.selectable_label(self.notifications.show_history, bell).on_hover_text("Notifications").clicked() {
+                        self.notifications.show_history = !self.notifications.show_history;
+                    }
+                    ui.separator();
                     ui.label(format!("Font: {} {}", self.settings.font_family, self.settings.font_size));
                 });
             });
@@ -308,22 +1103,38 @@ impl ClawpadApp {
             .resizable(true)
             .default_width(200.0)
             .show(ctx, |ui| {
-                ui.heading("Explorer");
+                ui.horizontal(|ui| {
+                    ui.heading("Explorer");
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("⌖").on_hover_text("Reveal Active File").clicked() {
+                            self.reveal_active_file();
+                        }
+                    });
+                });
                 ui.separator();
                 
+                let mut events = Vec::new();
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    let files = self.sidebar_files.clone();
-                    for path in files {
-                        let name = path.file_name().unwrap_or_default().to_string_lossy();
-                        if ui.selectable_label(false, name).clicked() {
-                            self.open_file(path);
+                    let active = self.documents[self.active_index].file_path.clone();
+                    for tree in &mut self.file_trees {
+                        events.extend(tree.show(ui, &mut self.settings, active.as_deref()));
+                    }
+                    if self.file_trees.is_empty() {
+                        ui.label("No folder is open.");
+                        if ui.button("Open Folder...").clicked()
+                            && let Some(folder) = rfd::FileDialog::new().pick_folder()
+                        {
+                            self.set_workspace_roots(vec![folder]);
                         }
                     }
                 });
+                for event in events {
+                    self.apply_tree_event(event);
+                }
                 
                 ui.with_layout(egui::Layout::bottom_up(egui::Align::Center), |ui| {
                     if ui.button("Refresh").clicked() {
-                        self.refresh_sidebar();
+                        self.refresh_trees();
                     }
                 });
             });
@@ -334,54 +1145,15 @@ impl ClawpadApp {
             .resizable(true)
             .default_width(300.0)
             .show(ctx, |ui| {
-                ui.heading("Global Search");
-                ui.horizontal(|ui| {
-                    let response = ui.text_edit_singleline(&mut self.search_query);
-                    if response.changed() || ui.button("Search").clicked() {
-                        self.perform_search();
-                    }
-                });
-                ui.separator();
-
-                egui::ScrollArea::vertical().show(ui, |ui| {
-                    let mut to_open = None;
-                    for result in &self.search_results {
-                        let file_name = result.path.file_name().unwrap_or_default().to_string_lossy();
-                        if ui.selectable_label(false, format!("{}:{} - {}", file_name, result.line_number, result.line_content)).clicked() {
-                            to_open = Some(result.path.clone());
-                        }
-                    }
-                    if let Some(path) = to_open {
-                        self.open_file(path);
-                    }
-                });
-            });
-    }
-
-    fn perform_search(&mut self) {
-        self.search_results.clear();
-        if self.search_query.len() < 2 { return; }
-
-        for entry in WalkDir::new(".").max_depth(3).into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path();
-            if path.is_file() {
-                if path.to_string_lossy().contains("target") || path.to_string_lossy().contains(".git") {
-                    continue;
+                let limits = (self.settings.search_max_depth, self.settings.search_max_results);
+                let clicked = self.search_panel.show(ui, &mut self.settings, &mut self.documents);
+                if limits != (self.settings.search_max_depth, self.settings.search_max_results) {
+                    self.save_settings();
                 }
-                if let Ok(content) = fs::read_to_string(path) {
-                    for (i, line) in content.lines().enumerate() {
-                        if line.contains(&self.search_query) {
-                            self.search_results.push(SearchResult {
-                                path: path.to_path_buf(),
-                                line_number: i + 1,
-                                line_content: line.trim().to_string(),
-                            });
-                        }
-                        if self.search_results.len() > 100 { break; }
-                    }
+                if let Some((source, chars)) = clicked {
+                    self.open_match(source, chars);
                 }
-            }
-        }
+            });
     }
 
     fn draw_central_panel(&mut self, ctx: &egui::Context) {
@@ -390,6 +1162,16 @@ impl ClawpadApp {
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(bg_color))
             .show(ctx, |ui| {
+                if self.active_doc().disk_changed {
+                    self.draw_disk_banner(ui);
+                    ui.separator();
+                }
+                if self.find.visible {
+                    let doc = &mut self.documents[self.active_index];
+                    self.find.show(ui, doc);
+                    ui.separator();
+                }
+
                 let available_size = ui.available_size();
                 if self.show_preview && self.active_doc().language == "Markdown" {
                     ui.columns(2, |columns| {
@@ -406,6 +1188,50 @@ impl ClawpadApp {
             });
     }
 
+    // Offered when the active document's file changed on disk under unsaved edits
+    fn draw_disk_banner(&mut self, ui: &mut egui::Ui) {
+        let doc = &mut self.documents[self.active_index];
+        let id = doc.id;
+        ui.horizontal(|ui| {
+            ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {} changed on disk.", doc.name()));
+            if ui.button("Reload").on_hover_text("Replace your changes with the file; undo brings them back").clicked()
+                && let Some(path) = &doc.file_path
+            {
+                match encoding::read_as(path, doc.encoding) {
+                    Ok(disk) => {
+                        doc.reload(&disk.text);
+                        note_invalid_bytes(doc, disk.had_errors, &mut self.notifications);
+                        self.disk_diff = None;
+                    }
+                    Err(error) => self.notifications.error(format!("Couldn't reload {}: {error}", path.display())),
+                }
+            }
+            if ui.button("Keep Mine").on_hover_text("Saving will overwrite the file").clicked() {
+                doc.disk_changed = false;
+                self.disk_diff = None;
+            }
+            let showing = self.disk_diff.as_ref().is_some_and(|(diff_id, _)| *diff_id == id);
+            if ui.selectable_label(showing, "Diff").clicked() {
+                self.disk_diff = None;
+                if !showing && let Some(path) = doc.file_path.clone() {
+                    match encoding::read_as(&path, doc.encoding) {
+                        Ok(disk) => {
+                            let disk = line_ending::normalize(&disk.text);
+                            self.disk_diff = Some((id, diff::unified(&disk, doc.text(), "on disk", "yours")));
+                        }
+                        Err(error) => self.notifications.error(format!("Couldn't read {}: {error}", path.display())),
+                    }
+                }
+            }
+        });
+
+        if let Some((diff_id, diff)) = &self.disk_diff
+            && *diff_id == id
+        {
+            egui::ScrollArea::vertical().max_height(200.0).id_salt("disk_diff").show(ui, |ui| diff::show_lines(ui, diff));
+        }
+    }
+
     fn draw_editor_with_minimap(&mut self, ui: &mut egui::Ui) {
         let available_size = ui.available_size();
         if self.show_minimap {
@@ -413,12 +1239,12 @@ impl ClawpadApp {
             let editor_width = (available_size.x - minimap_width - 10.0).max(0.0);
             
             ui.horizontal_top(|ui| {
-                ui.allocate_ui(egui::vec2(editor_width, available_size.y), |ui| {
-                    self.draw_editor(ui);
-                });
+                let editor = ui.allocate_ui(egui::vec2(editor_width, available_size.y), |ui| {
+                    self.draw_editor(ui)
+                }).inner;
                 
                 ui.allocate_ui(egui::vec2(minimap_width, available_size.y), |ui| {
-                    self.draw_minimap(ui);
+                    self.draw_minimap(ui, &editor);
                 });
             });
         } else {
@@ -426,97 +1252,112 @@ impl ClawpadApp {
         }
     }
 
-    fn draw_minimap(&mut self, ui: &mut egui::Ui) {
+    fn draw_minimap(&mut self, ui: &mut egui::Ui, editor: &EditorOutput) {
+        // Each line is drawn as thin bars, one pixel per char
+        const ROW: f32 = 2.0;
+        const MAX_COLUMNS: usize = 120;
+
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
         let doc = self.active_doc();
-        let content = doc.content_cache.clone();
-        
-        egui::ScrollArea::vertical()
-            .id_salt("minimap_scroll")
-            .show(ui, |ui| {
-                ui.disable(); // Read-only
-                let font_id = egui::FontId::monospace(2.0);
-                ui.add(
-                    egui::TextEdit::multiline(&mut content.as_str())
-                        .font(font_id)
-                        .frame(false)
-                        .desired_width(f32::INFINITY),
-                );
-            });
-    }
+        let total_lines = doc.rope.len_lines();
+        let visible_count = editor.visible_lines.len().max(1);
+        let minimap_lines = (rect.height() / ROW) as usize;
 
-    fn draw_editor(&mut self, ui: &mut egui::Ui) {
-        let font_size = self.settings.font_size;
-        let font_family = self.settings.font_family.clone();
-        let theme = if self.settings.theme_dark { "base16-ocean.dark" } else { "base16-ocean.light" }.to_string();
-        let highlighter = &self.highlighter;
-        
-        let active_index = self.active_index;
-        let doc = &mut self.documents[active_index];
-        let language = doc.language.clone();
-        
-        let font_id = if font_family == "monospace" {
-            egui::FontId::monospace(font_size)
+        // Scroll the minimap proportionally so the editor viewport is always inside it
+        let first = if total_lines <= minimap_lines {
+            0
         } else {
-            egui::FontId::new(font_size, egui::FontFamily::Name(font_family.into()))
+            let progress = editor.visible_lines.start as f32 / total_lines.saturating_sub(visible_count).max(1) as f32;
+            ((total_lines - minimap_lines) as f32 * progress.min(1.0)) as usize
         };
 
-        // Handle Ctrl+D for next occurrence
-        if ui.input(|i| i.modifiers.command && i.key_pressed(egui::Key::D)) {
-            if let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), egui::Id::new("editor")) {
-                let content = &doc.content_cache;
-                if let Some(range) = state.cursor.char_range() {
-                    let start = range.primary.index.min(range.secondary.index);
-                    let end = range.primary.index.max(range.secondary.index);
-                    if start != end {
-                        let selected = &content[start..end];
-                        if let Some(pos) = content[end..].find(selected) {
-                            let new_start = end + pos;
-                            let new_end = new_start + selected.len();
-                            state.cursor.set_char_range(Some(egui::text::CCursorRange::two(
-                                egui::text::CCursor::new(new_start),
-                                egui::text::CCursor::new(new_end)
-                            )));
-                            state.store(ui.ctx(), egui::Id::new("editor"));
-                        }
+        let painter = ui.painter_at(rect);
+        let color = ui.visuals().text_color().gamma_multiply(0.6);
+        for line in first..(first + minimap_lines).min(total_lines) {
+            let y = rect.top() + (line - first) as f32 * ROW;
+            let mut run_start = None;
+            for (column, ch) in doc.rope.line(line).chars().take(MAX_COLUMNS).chain(std::iter::once(' ')).enumerate() {
+                match (ch.is_whitespace(), run_start) {
+                    (false, None) => run_start = Some(column),
+                    (true, Some(start)) => {
+                        let bar = egui::Rect::from_min_max(
+                            egui::pos2(rect.left() + start as f32, y),
+                            egui::pos2(rect.left() + column as f32, y + ROW * 0.75),
+                        );
+                        painter.rect_filled(bar, 0.0, color);
+                        run_start = None;
                     }
+                    _ => {}
                 }
             }
         }
 
-        egui::ScrollArea::both() // Enable horizontal scroll too
-            .auto_shrink([false; 2])
-            .show(ui, |ui| {
-                let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
-                    let mut layout_job = highlighter.highlight(string, &language, &theme);
-                    layout_job.wrap.max_width = wrap_width;
-                    for section in &mut layout_job.sections {
-                        section.format.font_id = font_id.clone();
-                    }
-                    ui.fonts(|f| f.layout_job(layout_job))
-                };
+        // Search hits as marks along the right edge
+        let matches = self.find.matches(doc);
+        let from = matches.partition_point(|m| m.start < doc.rope.line_to_char(first));
+        let match_color = ui.visuals().warn_fg_color;
+        for found in &matches[from..] {
+            let line = doc.rope.char_to_line(found.start);
+            if line >= first + minimap_lines {
+                break;
+            }
+            let y = rect.top() + (line - first) as f32 * ROW;
+            let mark = egui::Rect::from_min_max(egui::pos2(rect.right() - 6.0, y), egui::pos2(rect.right(), y + ROW));
+            painter.rect_filled(mark, 0.0, match_color);
+        }
 
-                let text_edit = egui::TextEdit::multiline(&mut doc.content_cache)
-                    .id(egui::Id::new("editor"))
-                    .font(font_id.clone())
-                    .lock_focus(true)
-                    .desired_width(f32::INFINITY)
-                    .desired_rows(50)
-                    .frame(false) // No frame/border
-                    .layouter(&mut layouter);
-                
-                let output = ui.add_sized(ui.available_size(), text_edit);
+        let viewport = egui::Rect::from_x_y_ranges(
+            rect.x_range(),
+            rect.top() + (editor.visible_lines.start as f32 - first as f32) * ROW
+                ..=rect.top() + (editor.visible_lines.end as f32 - first as f32) * ROW,
+        );
+        painter.rect_filled(viewport, 0.0, ui.visuals().selection.bg_fill.gamma_multiply(0.3));
 
-                if output.changed() {
-                    doc.sync_from_cache();
-                }
-            });
+        if let Some(pos) = response.interact_pointer_pos() {
+            let line = first as f32 + (pos.y - rect.top()) / ROW - visible_count as f32 / 2.0;
+            self.active_doc_mut().scroll_offset.y = line.max(0.0) * editor.row_height;
+        }
+    }
+
+    fn draw_editor(&mut self, ui: &mut egui::Ui) -> EditorOutput {
+        let font_size = self.settings.font_size;
+        let font_family = self.settings.font_family.clone();
+        let theme = if self.settings.theme_dark { "base16-ocean.dark" } else { "base16-ocean.light" };
+
+        let font_id = if font_family == "monospace" {
+            egui::FontId::monospace(font_size)
+        } else {
+            egui::FontId::new(font_size, egui::FontFamily::Name(font_family.into()))
+        };
+
+        let doc = &mut self.documents[self.active_index];
+        let matches = self.find.matches(doc);
+        EditorView::new(doc, &self.highlighter, theme, font_id)
+            .search_matches(matches)
+            .show(ui)
     }
 
     fn draw_preview(&mut self, ui: &mut egui::Ui) {
-        let content = self.active_doc().content_cache.clone();
+        let content = self.documents[self.active_index].text();
         egui::ScrollArea::vertical().show(ui, |ui| {
             egui_commonmark::CommonMarkViewer::new()
-                .show(ui, &mut self.md_cache, &content);
+                .show(ui, &mut self.md_cache, content);
         });
     }
 }
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Flags a document read with bytes its encoding couldn't decode, so saving warns first
+fn note_invalid_bytes(doc: &mut Document, had_errors: bool, notifications: &mut Notifications) {
+    doc.invalid_bytes = had_errors;
+    if had_errors {
+        notifications.warning(format!(
+            "{} has bytes that aren't valid {}; they are shown as �.",
+            doc.name(),
+            doc.encoding.label()
+        ));
+    }
+}
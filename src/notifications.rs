@@ -0,0 +1,152 @@
+use egui::{Align2, Color32, Context, Id, Ui, Visuals};
+use std::time::{Duration, Instant};
+
+// Errors stay up longer since they usually need acting on
+const INFO_TOAST: Duration = Duration::from_secs(5);
+const ERROR_TOAST: Duration = Duration::from_secs(12);
+const MAX_TOASTS: usize = 4;
+const MAX_HISTORY: usize = 200;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn icon(self) -> &'static str {
+        match self {
+            Self::Info => "ℹ",
+            Self::Warning => "⚠",
+            Self::Error => "⛔",
+        }
+    }
+
+    fn color(self, visuals: &Visuals) -> Color32 {
+        match self {
+            Self::Info => visuals.text_color(),
+            Self::Warning => visuals.warn_fg_color,
+            Self::Error => visuals.error_fg_color,
+        }
+    }
+}
+
+struct Notification {
+    severity: Severity,
+    message: String,
+    at: Instant,
+    // Still shown as a toast; cleared when it times out or is dismissed
+    toast: bool,
+}
+
+/// Messages for the user: shown as toasts in the corner of the window and kept in a history
+/// panel.
+#[derive(Default)]
+pub struct Notifications {
+    history: Vec<Notification>,
+    // Notifications added since the history panel was last open
+    unread: usize,
+    pub show_history: bool,
+}
+
+impl Notifications {
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(Severity::Info, message.into());
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.push(Severity::Warning, message.into());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(Severity::Error, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, message: String) {
+        log::log!(
+            match severity {
+                Severity::Info => log::Level::Info,
+                Severity::Warning => log::Level::Warn,
+                Severity::Error => log::Level::Error,
+            },
+            "{message}"
+        );
+        if self.history.len() == MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(Notification { severity, message, at: Instant::now(), toast: true });
+        self.unread += 1;
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    /// Draws the toasts and, when open, the history panel.
+    pub fn show(&mut self, ctx: &Context) {
+        for notification in &mut self.history {
+            let duration = if notification.severity == Severity::Error { ERROR_TOAST } else { INFO_TOAST };
+            if notification.at.elapsed() > duration {
+                notification.toast = false;
+            }
+        }
+
+        egui::Area::new(Id::new("toasts"))
+            .anchor(Align2::RIGHT_BOTTOM, egui::vec2(-12.0, -36.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                ui.set_max_width(360.0);
+                for notification in self.history.iter_mut().rev().filter(|n| n.toast).take(MAX_TOASTS) {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            show_message(ui, notification);
+                            if ui.small_button("×").on_hover_text("Dismiss").clicked() {
+                                notification.toast = false;
+                            }
+                        });
+                    });
+                }
+            });
+
+        if self.show_history {
+            self.unread = 0;
+        }
+        let mut open = self.show_history;
+        egui::Window::new("Notifications")
+            .open(&mut open)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                if self.history.is_empty() {
+                    ui.label("No notifications.");
+                    return;
+                }
+                if ui.button("Clear All").clicked() {
+                    self.history.clear();
+                }
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for notification in self.history.iter().rev() {
+                        ui.horizontal_wrapped(|ui| {
+                            show_message(ui, notification);
+                            ui.weak(age(notification.at.elapsed()));
+                        });
+                    }
+                });
+            });
+        self.show_history = open;
+    }
+}
+
+fn show_message(ui: &mut Ui, notification: &Notification) {
+    ui.colored_label(notification.severity.color(ui.visuals()), notification.severity.icon());
+    ui.label(&notification.message);
+}
+
+fn age(elapsed: Duration) -> String {
+    match elapsed.as_secs() {
+        0..60 => "just now".to_string(),
+        secs @ 60..3600 => format!("{} min ago", secs / 60),
+        secs => format!("{} h ago", secs / 3600),
+    }
+}
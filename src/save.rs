@@ -0,0 +1,163 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes a file so that it holds either its old or its new contents, never a mix: the data goes
+/// to a temporary file next to it, which is synced and then renamed over the original. A symlink
+/// is followed so its target is replaced rather than the link, and the original's permissions are
+/// kept. With `backup`, the old contents are first copied to `file~`.
+pub fn write_atomic(path: &Path, backup: bool, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let target = resolve_symlinks(path);
+    let existing = fs::metadata(&target).ok();
+    if backup && existing.is_some() {
+        fs::copy(&target, backup_path(&target))?;
+    }
+
+    let (temp_path, temp) = create_temp(&target)?;
+    let result = (|| {
+        let mut writer = BufWriter::new(temp);
+        write(&mut writer)?;
+        let temp = writer.into_inner().map_err(io::IntoInnerError::into_error)?;
+        if let Some(metadata) = &existing {
+            temp.set_permissions(metadata.permissions())?;
+        }
+        temp.sync_all()?;
+        fs::rename(&temp_path, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        return result;
+    }
+    sync_dir(&target);
+    Ok(())
+}
+
+// The file a chain of symlinks points to; a dangling link is written through like `File::create` does
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    // Gives up on loops
+    for _ in 0..32 {
+        match fs::read_link(&path) {
+            Ok(link) => path = path.parent().unwrap_or(Path::new("")).join(link),
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push("~");
+    PathBuf::from(name)
+}
+
+// A new hidden file in the same directory, so the rename stays on one filesystem
+fn create_temp(target: &Path) -> io::Result<(PathBuf, File)> {
+    let dir = target.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut attempt = 0;
+    loop {
+        let temp_path = dir.join(format!(".{name}.{}-{attempt}.tmp", std::process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+// Makes the rename itself durable; only possible, and needed, on Unix
+fn sync_dir(target: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = target.parent()
+        && let Ok(dir) = File::open(if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+    {
+        let _ = dir.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = target;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clawpad-save-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, backup: bool, text: &str) -> io::Result<()> {
+        write_atomic(path, backup, |writer| writer.write_all(text.as_bytes()))
+    }
+
+    #[test]
+    fn replaces_the_contents_without_leaving_a_temporary_file() {
+        let dir = temp_dir("replace");
+        let file = dir.join("notes.txt");
+        write(&file, false, "new file").unwrap();
+        fs::write(&file, "old").unwrap();
+        write(&file, false, "new").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_write_keeps_the_original() {
+        let dir = temp_dir("fail");
+        let file = dir.join("notes.txt");
+        fs::write(&file, "old").unwrap();
+        let result = write_atomic(&file, false, |writer| {
+            writer.write_all(b"half")?;
+            Err(io::Error::other("disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_keeps_the_old_contents() {
+        let dir = temp_dir("backup");
+        let file = dir.join("notes.txt");
+        write(&file, true, "first").unwrap();
+        assert!(!dir.join("notes.txt~").exists());
+        write(&file, true, "second").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("notes.txt~")).unwrap(), "first");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "second");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("permissions");
+        let file = dir.join("run.sh");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o751)).unwrap();
+        write(&file, false, "new").unwrap();
+        assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o751);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        let dir = temp_dir("symlinks");
+        fs::create_dir(dir.join("real")).unwrap();
+        fs::write(dir.join("real/target.txt"), "old").unwrap();
+        // A relative link to a relative link
+        std::os::unix::fs::symlink("real/target.txt", dir.join("inner")).unwrap();
+        std::os::unix::fs::symlink("inner", dir.join("outer")).unwrap();
+        write(&dir.join("outer"), false, "new").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("real/target.txt")).unwrap(), "new");
+        assert!(fs::symlink_metadata(dir.join("outer")).unwrap().file_type().is_symlink());
+        assert!(fs::symlink_metadata(dir.join("inner")).unwrap().file_type().is_symlink());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,609 @@
+use crate::document::Document;
+use crate::encoding;
+use crate::find::SearchOptions;
+use crate::save;
+use crate::walk::{IgnoreRules, Walk};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use ropey::Rope;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+// Files queued ahead of the workers
+const QUEUE_LEN: usize = 256;
+
+/// What the workspace search panel is looking for.
+#[derive(Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    pub text: String,
+    pub options: SearchOptions,
+    // Comma-separated globs; `!glob` in the include list excludes
+    pub include: String,
+    pub exclude: String,
+}
+
+pub struct SearchResult {
+    pub line_number: usize,
+    // The whole line, without its line break
+    pub line_content: String,
+    pub matches: Vec<LineMatch>,
+}
+
+/// Where a match is: bytes within its line, and chars within the file for selecting it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LineMatch {
+    pub bytes: Range<usize>,
+    pub chars: Range<usize>,
+}
+
+/// Where a group of results was found.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum Source {
+    File(PathBuf),
+    // An untitled tab, by document id
+    Untitled(u64),
+}
+
+/// An open document, searched instead of its file on disk.
+pub struct Buffer {
+    pub id: u64,
+    pub path: Option<PathBuf>,
+    pub rope: Rope,
+}
+
+impl Buffer {
+    pub fn new(doc: &Document) -> Self {
+        Self { id: doc.id, path: doc.file_path.clone(), rope: doc.rope.clone() }
+    }
+}
+
+/// Matching lines of one file or buffer.
+pub struct FileResults {
+    pub source: Source,
+    // Shown greyed out; only searched when ignored files are shown
+    pub ignored: bool,
+    pub match_count: usize,
+    pub results: Vec<SearchResult>,
+}
+
+/// Include/exclude globs, matched against paths relative to the workspace folder they are in.
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    pub fn new(include: &str, exclude: &str) -> Result<Self, globset::Error> {
+        let mut includes = GlobSetBuilder::new();
+        let mut excludes = GlobSetBuilder::new();
+        let mut has_includes = false;
+        for pattern in split_globs(include) {
+            match pattern.strip_prefix('!') {
+                Some(pattern) => add_glob(&mut excludes, pattern)?,
+                None => {
+                    add_glob(&mut includes, pattern)?;
+                    has_includes = true;
+                }
+            }
+        }
+        for pattern in split_globs(exclude) {
+            add_glob(&mut excludes, pattern.strip_prefix('!').unwrap_or(pattern))?;
+        }
+        Ok(Self {
+            include: has_includes.then(|| includes.build()).transpose()?,
+            exclude: excludes.build()?,
+        })
+    }
+
+    /// Whether a directory should not be walked into.
+    pub fn excludes_dir(&self, relative: &Path) -> bool {
+        self.exclude.is_match(relative)
+    }
+
+    pub fn matches_file(&self, relative: &Path) -> bool {
+        !self.exclude.is_match(relative) && self.include.as_ref().is_none_or(|include| include.is_match(relative))
+    }
+}
+
+fn split_globs(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|pattern| !pattern.is_empty())
+}
+
+// A glob without a slash matches at any depth, like in .gitignore, and a matching directory
+// covers everything below it
+fn add_glob(set: &mut GlobSetBuilder, pattern: &str) -> Result<(), globset::Error> {
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+    let base = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if pattern.contains('/') => pattern.to_string(),
+        None => format!("**/{pattern}"),
+    };
+    for glob in [base.clone(), format!("{base}/**")] {
+        set.add(GlobBuilder::new(&glob).literal_separator(true).build()?);
+    }
+    Ok(())
+}
+
+// A line without its line break
+fn line_body(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => line,
+    }
+}
+
+/// Lines of `content` matching `regex`, or None if there are none.
+pub fn search_text(source: Source, ignored: bool, content: &str, regex: &Regex) -> Option<FileResults> {
+    let mut match_count = 0;
+    let mut results = Vec::new();
+    // Chars before the current line, counting `\r\n` as one like the documents it opens in
+    let mut line_start = 0;
+    for (i, line_with_break) in content.split_inclusive('\n').enumerate() {
+        let line = line_body(line_with_break);
+        let matches: Vec<LineMatch> = regex
+            .find_iter(line)
+            .filter(|m| !m.is_empty())
+            .map(|m| {
+                let start = line_start + line[..m.start()].chars().count();
+                LineMatch { bytes: m.range(), chars: start..start + m.as_str().chars().count() }
+            })
+            .collect();
+        line_start += line.chars().count() + usize::from(line_with_break.ends_with('\n'));
+        if !matches.is_empty() {
+            match_count += matches.len();
+            results.push(SearchResult {
+                line_number: i + 1,
+                line_content: line.to_string(),
+                matches,
+            });
+        }
+    }
+    (match_count > 0).then_some(FileResults { source, ignored, match_count, results })
+}
+
+/// Replacements within one line, checked against the text the line had when they were computed.
+pub struct LineEdit {
+    pub line_number: usize,
+    pub expected: String,
+    // Byte ranges within the line, front to back, and their replacements
+    pub replacements: Vec<(Range<usize>, String)>,
+}
+
+fn stale_line(line_number: usize) -> String {
+    format!("line {line_number} changed since the search")
+}
+
+/// Applies `edits` to `content`, failing if a line no longer reads as expected.
+pub fn replace_in_text(content: &str, edits: &[LineEdit]) -> Result<String, String> {
+    let mut edits = edits.iter().peekable();
+    let mut replaced = String::with_capacity(content.len());
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        let Some(edit) = edits.next_if(|edit| edit.line_number == i + 1) else {
+            replaced.push_str(line);
+            continue;
+        };
+        let body = line_body(line);
+        if body != edit.expected {
+            return Err(stale_line(edit.line_number));
+        }
+        let mut end = 0;
+        for (range, text) in &edit.replacements {
+            replaced.push_str(&body[end..range.start]);
+            replaced.push_str(text);
+            end = range.end;
+        }
+        replaced.push_str(&line[end..]);
+    }
+    match edits.next() {
+        Some(edit) => Err(stale_line(edit.line_number)),
+        None => Ok(replaced),
+    }
+}
+
+/// Applies `edits` to a file that isn't open, writing it back in the encoding it was read in.
+pub fn replace_in_file(path: &Path, edits: &[LineEdit], backup: bool) -> Result<(), String> {
+    let decoded = encoding::read(path).map_err(|e| e.to_string())?;
+    // Saving would write U+FFFD over them
+    if decoded.had_errors {
+        return Err(format!("it has bytes that aren't valid {}", decoded.encoding.label()));
+    }
+    let content = replace_in_text(&decoded.text, edits)?;
+    let bytes = decoded.encoding.encode(&content).map_err(|e| e.to_string())?;
+    save::write_atomic(path, backup, |writer| writer.write_all(&bytes)).map_err(|e| e.to_string())
+}
+
+/// Applies `edits` to an open document as a single undo step, failing if a line no longer reads as expected.
+pub fn replace_in_document(doc: &mut Document, edits: &[LineEdit]) -> Result<(), String> {
+    for edit in edits {
+        if edit.line_number > doc.rope.len_lines() || doc.line_text(edit.line_number - 1) != edit.expected {
+            return Err(stale_line(edit.line_number));
+        }
+    }
+
+    let before = doc.cursor_state();
+    doc.history.break_group();
+    // Back to front so earlier positions stay valid
+    for edit in edits.iter().rev() {
+        let line_start = doc.rope.line_to_byte(edit.line_number - 1);
+        for (range, text) in edit.replacements.iter().rev() {
+            let start = doc.rope.byte_to_char(line_start + range.start);
+            doc.delete(start..doc.rope.byte_to_char(line_start + range.end));
+            doc.insert(start, text);
+        }
+    }
+    let caret = doc.selected_range().start.min(doc.rope.len_chars());
+    doc.set_selection((caret, caret));
+    doc.commit_edits(before);
+    doc.history.break_group();
+    Ok(())
+}
+
+/// A workspace search running on a background worker pool; dropping it cancels the search.
+pub struct SearchTask {
+    shared: Arc<Shared>,
+    results: Receiver<FileResults>,
+    finished: bool,
+}
+
+// What the walker feeds the workers
+enum Job {
+    File { path: PathBuf, ignored: bool },
+    // An open document's text, searched instead of its file
+    Buffer { source: Source, ignored: bool, rope: Rope },
+}
+
+// Where the walker looks
+struct Scope {
+    // Workspace folders, each with its own ignore rules
+    roots: Vec<(PathBuf, IgnoreRules)>,
+    filter: PathFilter,
+    include_ignored: bool,
+    max_depth: usize,
+}
+
+// State shared by the walker, the workers and the UI
+struct Shared {
+    regex: Regex,
+    max_results: usize,
+    cancelled: AtomicBool,
+    truncated: AtomicBool,
+    scanned: AtomicUsize,
+    found: AtomicUsize,
+}
+
+impl SearchTask {
+    /// Starts searching the files under `roots`, up to `max_depth` levels deep, stopping after `max_results` matching lines.
+    /// Open `buffers` are searched instead of their files, and untitled ones on their own.
+    pub fn start(
+        roots: Vec<(PathBuf, IgnoreRules)>,
+        query: &SearchQuery,
+        include_ignored: bool,
+        buffers: Vec<Buffer>,
+        max_depth: usize,
+        max_results: usize,
+    ) -> Result<Self, String> {
+        let regex = query.options.build(&query.text).map_err(|e| e.to_string())?;
+        let filter = PathFilter::new(&query.include, &query.exclude).map_err(|e| e.to_string())?;
+        let scope = Scope { roots, filter, include_ignored, max_depth };
+        let shared = Arc::new(Shared {
+            regex,
+            max_results,
+            cancelled: AtomicBool::new(false),
+            truncated: AtomicBool::new(false),
+            scanned: AtomicUsize::new(0),
+            found: AtomicUsize::new(0),
+        });
+
+        let (jobs, queue) = mpsc::sync_channel(QUEUE_LEN);
+        let queue = Arc::new(Mutex::new(queue));
+        let (sender, results) = mpsc::channel();
+        let walker_shared = shared.clone();
+        std::thread::Builder::new()
+            .name("search-walker".to_string())
+            .spawn(move || walk(&scope, buffers, &walker_shared, jobs))
+            .map_err(|e| e.to_string())?;
+        let workers = std::thread::available_parallelism().map_or(4, |n| n.get()).min(8);
+        for _ in 0..workers {
+            let (shared, queue, sender) = (shared.clone(), queue.clone(), sender.clone());
+            std::thread::Builder::new()
+                .name("search-worker".to_string())
+                .spawn(move || run_worker(&shared, &queue, &sender))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(Self { shared, results, finished: false })
+    }
+
+    /// Files found since the last call.
+    pub fn poll(&mut self) -> Vec<FileResults> {
+        let mut files = Vec::new();
+        loop {
+            match self.results.try_recv() {
+                Ok(file) => files.push(file),
+                Err(TryRecvError::Empty) => break,
+                // Every worker has exited
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    break;
+                }
+            }
+        }
+        files
+    }
+
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::Relaxed) && !self.truncated()
+    }
+
+    /// Stopped early because the result limit was reached.
+    pub fn truncated(&self) -> bool {
+        self.shared.truncated.load(Ordering::Relaxed)
+    }
+
+    pub fn scanned(&self) -> usize {
+        self.shared.scanned.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for SearchTask {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// Paths compare equal here however they were spelled when opened
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().or_else(|_| std::path::absolute(path)).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Feeds the untitled buffers, then the files under each root, into the workers' queue
+fn walk(scope: &Scope, buffers: Vec<Buffer>, shared: &Shared, jobs: SyncSender<Job>) {
+    let mut open = HashMap::new();
+    for buffer in buffers {
+        let job = match buffer.path {
+            Some(path) => {
+                open.insert(absolute(&path), (path, buffer.rope));
+                continue;
+            }
+            None => Job::Buffer { source: Source::Untitled(buffer.id), ignored: false, rope: buffer.rope },
+        };
+        if jobs.send(job).is_err() {
+            return;
+        }
+    }
+
+    for (root, rules) in &scope.roots {
+        let absolute_root = absolute(root);
+        let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+        let walker = Walk::new(root, rules, scope.max_depth, scope.include_ignored)
+            .skip_dirs(|dir| scope.filter.excludes_dir(&relative(dir)));
+        for entry in walker {
+            if shared.cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let relative = relative(&entry.path);
+            if entry.is_dir || !scope.filter.matches_file(&relative) {
+                continue;
+            }
+            // Results of an open file point at its document's path
+            let job = match open.get(&absolute_root.join(&relative)) {
+                Some((path, rope)) => Job::Buffer { source: Source::File(path.clone()), ignored: entry.ignored, rope: rope.clone() },
+                None => Job::File { path: entry.path, ignored: entry.ignored },
+            };
+            if jobs.send(job).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn run_worker(shared: &Shared, queue: &Mutex<Receiver<Job>>, sender: &Sender<FileResults>) {
+    loop {
+        let Ok(job) = queue.lock().unwrap_or_else(|e| e.into_inner()).recv() else {
+            return;
+        };
+        if shared.cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        let found = match job {
+            Job::File { path, ignored } => search_file(path, ignored, &shared.regex),
+            Job::Buffer { source, ignored, rope } => search_text(source, ignored, &rope.to_string(), &shared.regex),
+        };
+        shared.scanned.fetch_add(1, Ordering::Relaxed);
+        let Some(mut file) = found else {
+            continue;
+        };
+
+        // Claim a share of the result limit; whoever crosses it stops the search
+        let taken = shared.found.fetch_add(file.results.len(), Ordering::Relaxed);
+        let remaining = shared.max_results.saturating_sub(taken);
+        if file.results.len() > remaining {
+            file.results.truncate(remaining);
+            shared.truncated.store(true, Ordering::Relaxed);
+            shared.cancelled.store(true, Ordering::Relaxed);
+        }
+        if file.results.is_empty() || sender.send(file).is_err() {
+            return;
+        }
+    }
+}
+
+// Searches one file on disk, decoded like it would be when opened; binary files are skipped
+fn search_file(path: PathBuf, ignored: bool, regex: &Regex) -> Option<FileResults> {
+    let decoded = encoding::read(&path).ok()?;
+    search_text(Source::File(path), ignored, &decoded.text, regex)
+}
+
+/// Formats a count with thousands separators, e.g. 1,240.
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::TextEncoding;
+
+    fn match_chars(content: &str, pattern: &str) -> Vec<Range<usize>> {
+        let regex = Regex::new(pattern).unwrap();
+        search_text(Source::File(PathBuf::from("test.txt")), false, content, &regex)
+            .map(|file| file.results.into_iter().flat_map(|result| result.matches).map(|found| found.chars).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn search_text_counts_crlf_as_one_char() {
+        assert_eq!(match_chars("ab\r\ncd\r\nef", "cd|ef"), vec![3..5, 6..8]);
+        assert_eq!(match_chars("ab\ncd\nef", "cd|ef"), vec![3..5, 6..8]);
+    }
+
+    #[test]
+    fn search_text_without_matches_is_none() {
+        let regex = Regex::new("x").unwrap();
+        for content in ["", "abc", "\r\n"] {
+            assert!(search_text(Source::File(PathBuf::from("test.txt")), false, content, &regex).is_none());
+        }
+    }
+
+    #[test]
+    fn path_filter_globs_without_a_slash_match_at_any_depth() {
+        let filter = PathFilter::new("*.rs", "").unwrap();
+        assert!(filter.matches_file(Path::new("main.rs")));
+        assert!(filter.matches_file(Path::new("src/deep/lib.rs")));
+        assert!(!filter.matches_file(Path::new("src/notes.md")));
+    }
+
+    #[test]
+    fn path_filter_anchored_and_directory_globs() {
+        let filter = PathFilter::new("/src", "target/, ./docs").unwrap();
+        assert!(filter.matches_file(Path::new("src/main.rs")));
+        assert!(!filter.matches_file(Path::new("lib/src/main.rs")));
+        assert!(filter.excludes_dir(Path::new("target")));
+        assert!(filter.excludes_dir(Path::new("sub/target")));
+        assert!(filter.excludes_dir(Path::new("docs")));
+        assert!(!filter.excludes_dir(Path::new("src")));
+    }
+
+    #[test]
+    fn path_filter_negated_include_excludes() {
+        let filter = PathFilter::new("src/**, !*.lock", "").unwrap();
+        assert!(filter.matches_file(Path::new("src/a.rs")));
+        assert!(!filter.matches_file(Path::new("src/Cargo.lock")));
+        assert!(!filter.matches_file(Path::new("README.md")));
+    }
+
+    #[test]
+    fn path_filter_empty_lists_match_everything() {
+        let filter = PathFilter::new(" , ", "").unwrap();
+        assert!(filter.matches_file(Path::new("any/file.txt")));
+        assert!(!filter.excludes_dir(Path::new("any")));
+    }
+
+    #[test]
+    fn path_filter_rejects_bad_globs() {
+        assert!(PathFilter::new("a[", "").is_err());
+        assert!(PathFilter::new("", "{a").is_err());
+    }
+
+    #[test]
+    fn add_glob_covers_everything_below_a_match() {
+        let mut builder = GlobSetBuilder::new();
+        add_glob(&mut builder, "build").unwrap();
+        let set = builder.build().unwrap();
+        assert!(set.is_match("build"));
+        assert!(set.is_match("a/build/out/x.o"));
+        assert!(!set.is_match("builds/x"));
+    }
+
+    fn edit(line_number: usize, expected: &str, replacements: &[(Range<usize>, &str)]) -> LineEdit {
+        LineEdit {
+            line_number,
+            expected: expected.to_string(),
+            replacements: replacements.iter().map(|(range, text)| (range.clone(), text.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn replace_in_text_keeps_crlf_line_breaks() {
+        let edits = [edit(1, "foo foo", &[(0..3, "bar"), (4..7, "baz")]), edit(3, "foo", &[(0..3, "qux")])];
+        assert_eq!(replace_in_text("foo foo\r\nkeep\r\nfoo", &edits).unwrap(), "bar baz\r\nkeep\r\nqux");
+    }
+
+    #[test]
+    fn replace_in_text_without_edits_is_unchanged() {
+        assert_eq!(replace_in_text("", &[]).unwrap(), "");
+        assert_eq!(replace_in_text("a\nb\n", &[]).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn replace_in_text_fails_on_changed_lines() {
+        assert!(replace_in_text("foo\n", &[edit(1, "bar", &[(0..3, "x")])]).is_err());
+        assert!(replace_in_text("foo\n", &[edit(2, "foo", &[(0..3, "x")])]).is_err());
+        assert!(replace_in_text("", &[edit(1, "", &[(0..0, "x")])]).is_err());
+    }
+
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("clawpad-search-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn search_file_skips_the_bom_and_reads_utf16() {
+        let regex = Regex::new("wörld").unwrap();
+        let with_bom = temp_file("bom.txt", "\u{feff}hello\nwörld\n".as_bytes());
+        let utf16 = TextEncoding { encoding: encoding_rs::UTF_16LE, bom: true }.encode("hello\nwörld\n").unwrap();
+        let utf16 = temp_file("utf16.txt", &utf16);
+        for path in [with_bom, utf16] {
+            let file = search_file(path.clone(), false, &regex).unwrap();
+            let chars: Vec<_> = file.results.iter().flat_map(|result| &result.matches).map(|found| found.chars.clone()).collect();
+            assert_eq!(chars, vec![6..11], "{}", path.display());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn search_file_skips_binary_files() {
+        let path = temp_file("binary.bin", b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00world");
+        assert!(search_file(path.clone(), false, &Regex::new("w").unwrap()).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replace_in_file_keeps_the_encoding_and_line_breaks() {
+        let encoding = TextEncoding { encoding: encoding_rs::UTF_16LE, bom: true };
+        let path = temp_file("replace.txt", &encoding.encode("foo\r\nkeep\r\nfoo").unwrap());
+        replace_in_file(&path, &[edit(1, "foo", &[(0..3, "bär")]), edit(3, "foo", &[(0..3, "qux")])], false).unwrap();
+        let decoded = encoding::read(&path).unwrap();
+        assert_eq!(decoded.encoding, encoding);
+        assert_eq!(decoded.text, "bär\r\nkeep\r\nqux");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replace_in_file_refuses_undecodable_files() {
+        let path = temp_file("invalid.txt", b"\xef\xbb\xbffoo \xff\n");
+        assert!(replace_in_file(&path, &[edit(1, "foo \u{fffd}", &[(0..3, "bar")])], false).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xef\xbb\xbffoo \xff\n");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+use crate::document::Document;
+use crate::search::{self, Buffer, FileResults, LineEdit, LineMatch, SearchQuery, SearchTask, Source};
+use crate::settings::Settings;
+use crate::walk::IgnoreRules;
+use egui::text::LayoutJob;
+use egui::{Color32, FontId, TextFormat, Ui};
+use regex::Regex;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Outcome of the last Replace All.
+struct ReplaceReport {
+    replaced: usize,
+    files: usize,
+    // Display name and error of each file that couldn't be changed
+    failed: Vec<(String, String)>,
+}
+
+/// Workspace search and replace panel.
+#[derive(Default)]
+pub struct SearchPanel {
+    query: SearchQuery,
+    results: Vec<FileResults>,
+    task: Option<SearchTask>,
+    error: Option<String>,
+    // Compiled query the results were found with, for expanding replacements
+    regex: Option<Regex>,
+    show_replace: bool,
+    replacement: String,
+    // Matches unticked in the replace preview, as (source, line number, index within the line)
+    excluded: HashSet<(Source, usize, usize)>,
+    report: Option<ReplaceReport>,
+    // Match last jumped to, as (source, line number, index within the line)
+    current: Option<(Source, usize, usize)>,
+    // Scroll the current match into view on the next frame
+    reveal_current: bool,
+    // Workspace folders to search
+    roots: Vec<PathBuf>,
+}
+
+impl SearchPanel {
+    /// Searches these workspace folders from the next search on.
+    pub fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        self.roots = roots;
+    }
+
+    /// Restarts the search, which drops and so cancels the running one.
+    pub fn search(&mut self, settings: &Settings, documents: &[Document]) {
+        self.results.clear();
+        self.task = None;
+        self.error = None;
+        self.regex = None;
+        self.excluded.clear();
+        self.current = None;
+        if self.query.text.is_empty() {
+            return;
+        }
+
+        let roots = self.roots
+            .iter()
+            .map(|root| (root.clone(), IgnoreRules::new(root, &settings.exclude_patterns)))
+            .collect();
+        let (max_depth, max_results) = (settings.search_max_depth, settings.search_max_results);
+        let buffers = documents.iter().map(Buffer::new).collect();
+        match SearchTask::start(roots, &self.query, settings.show_ignored, buffers, max_depth, max_results) {
+            Ok(task) => {
+                self.task = Some(task);
+                self.regex = self.query.options.build(&self.query.text).ok();
+            }
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    /// Moves results streamed in by the search workers into the panel, kept sorted with files by path before untitled tabs.
+    pub fn poll(&mut self) {
+        let Some(task) = &mut self.task else {
+            return;
+        };
+        for file in task.poll() {
+            let index = self.results.partition_point(|f| f.source < file.source);
+            self.results.insert(index, file);
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+
+    /// Moves to the next or previous match, wrapping around, and returns where it is.
+    pub fn step(&mut self, forward: bool) -> Option<(Source, Range<usize>)> {
+        let all: Vec<(&Source, usize, usize, &LineMatch)> = self.results
+            .iter()
+            .flat_map(|file| file.results.iter().map(move |result| (file, result)))
+            .flat_map(|(file, result)| {
+                result.matches.iter().enumerate().map(move |(index, found)| (&file.source, result.line_number, index, found))
+            })
+            .collect();
+        if all.is_empty() {
+            return None;
+        }
+        let position = self.current.as_ref().and_then(|(source, line, index)| {
+            all.iter().position(|(s, l, i, _)| *s == source && l == line && i == index)
+        });
+        let next = match (position, forward) {
+            (Some(position), true) => (position + 1) % all.len(),
+            (Some(position), false) => (position + all.len() - 1) % all.len(),
+            (None, true) => 0,
+            (None, false) => all.len() - 1,
+        };
+        let (source, line, index, found) = all[next];
+        let jump = (source.clone(), found.chars.clone());
+        self.current = Some((source.clone(), line, index));
+        self.reveal_current = true;
+        Some(jump)
+    }
+
+    fn is_current(&self, source: &Source, line_number: usize, index: Option<usize>) -> bool {
+        self.current.as_ref().is_some_and(|(s, l, i)| s == source && *l == line_number && index.is_none_or(|index| *i == index))
+    }
+
+    fn is_included(&self, source: &Source, line_number: usize, index: usize) -> bool {
+        !self.excluded.contains(&(source.clone(), line_number, index))
+    }
+
+    // Replacement text for a match, or None if the line no longer matches there
+    fn replacement_for(&self, line: &str, start: usize) -> Option<String> {
+        let captures = self.regex.as_ref()?.captures_at(line, start)?;
+        Some(self.query.options.expand(&captures, &self.replacement))
+    }
+
+    // Ticked replacements of one file, line by line
+    fn edits_for(&self, file: &FileResults) -> Vec<LineEdit> {
+        file.results
+            .iter()
+            .filter_map(|result| {
+                let replacements: Vec<_> = result.matches
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| self.is_included(&file.source, result.line_number, *index))
+                    .filter_map(|(_, found)| Some((found.bytes.clone(), self.replacement_for(&result.line_content, found.bytes.start)?)))
+                    .collect();
+                (!replacements.is_empty()).then(|| LineEdit {
+                    line_number: result.line_number,
+                    expected: result.line_content.clone(),
+                    replacements,
+                })
+            })
+            .collect()
+    }
+
+    // Open documents get undoable edits, other files are rewritten on disk
+    fn replace_all(&mut self, settings: &Settings, documents: &mut [Document]) {
+        let mut report = ReplaceReport { replaced: 0, files: 0, failed: Vec::new() };
+        for file in &self.results {
+            let edits = self.edits_for(file);
+            if edits.is_empty() {
+                continue;
+            }
+            let doc = documents.iter_mut().find(|doc| match &file.source {
+                Source::File(path) => doc.file_path.as_deref().is_some_and(|open| same_file(open, path)),
+                Source::Untitled(id) => doc.id == *id,
+            });
+            let result = match (doc, &file.source) {
+                (Some(doc), _) => search::replace_in_document(doc, &edits),
+                (None, Source::File(path)) => search::replace_in_file(path, &edits, settings.backup_on_save),
+                (None, Source::Untitled(_)) => Err("the tab was closed".to_string()),
+            };
+            match result {
+                Ok(()) => {
+                    report.replaced += edits.iter().map(|edit| edit.replacements.len()).sum::<usize>();
+                    report.files += 1;
+                }
+                Err(error) => report.failed.push((source_name(&file.source, documents), error)),
+            }
+        }
+        self.report = Some(report);
+        self.search(settings, documents);
+    }
+
+    /// Draws the panel; returns the file and char range of a clicked match. The search limits
+    /// are edited in `settings`, which are left for the caller to save.
+    pub fn show(&mut self, ui: &mut Ui, settings: &mut Settings, documents: &mut [Document]) -> Option<(Source, Range<usize>)> {
+        ui.heading("Global Search");
+        let before = self.query.clone();
+        ui.horizontal(|ui| {
+            let toggle = if self.show_replace { "⏷" } else { "⏵" };
+            if ui.small_button(toggle).on_hover_text("Toggle Replace").clicked() {
+                self.show_replace = !self.show_replace;
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.query.text).hint_text("Search").desired_width(160.0));
+            let options = &mut self.query.options;
+            ui.toggle_value(&mut options.case_sensitive, "Aa").on_hover_text("Match Case");
+            ui.toggle_value(&mut options.whole_word, "ab").on_hover_text("Match Whole Word");
+            ui.toggle_value(&mut options.regex, ".*").on_hover_text("Use Regular Expression");
+        });
+        if self.show_replace {
+            ui.horizontal(|ui| {
+                ui.add_space(ui.spacing().interact_size.y + ui.spacing().item_spacing.x);
+                let hint = if self.query.options.regex { "Replace ($1 for groups)" } else { "Replace" };
+                ui.add(egui::TextEdit::singleline(&mut self.replacement).hint_text(hint).desired_width(160.0));
+                let found: usize = self.results.iter().flat_map(|file| &file.results).map(|result| result.matches.len()).sum();
+                let pending = found - self.excluded.len();
+                let enabled = pending > 0 && !self.is_running();
+                if ui.add_enabled(enabled, egui::Button::new("Replace All")).on_hover_text(format!("Replace {pending} ticked matches")).clicked() {
+                    self.replace_all(settings, documents);
+                }
+            });
+        }
+        ui.add(egui::TextEdit::singleline(&mut self.query.include).hint_text("files to include (e.g. src/**/*.rs, !*.lock)"));
+        ui.add(egui::TextEdit::singleline(&mut self.query.exclude).hint_text("files to exclude"));
+
+        let mut limits_changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Depth");
+            limits_changed |= ui.add(egui::DragValue::new(&mut settings.search_max_depth).range(1..=256)).changed();
+            ui.label("Max results");
+            limits_changed |= ui.add(egui::DragValue::new(&mut settings.search_max_results).range(1..=100_000)).changed();
+        });
+
+        let running = self.is_running();
+        let mut search_clicked = false;
+        ui.horizontal(|ui| {
+            search_clicked = ui.button("Search").clicked();
+            if running {
+                if ui.button("Stop").clicked()
+                    && let Some(task) = &self.task
+                {
+                    task.cancel();
+                }
+                ui.spinner();
+            }
+        });
+        if self.query != before || limits_changed || search_clicked {
+            self.report = None;
+            self.search(settings, documents);
+        }
+        ui.separator();
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        } else if let Some(task) = &self.task {
+            let matches: usize = self.results.iter().map(|f| f.match_count).sum();
+            let mut summary = format!(
+                "{} results in {} files, scanned {} files",
+                search::format_count(matches),
+                search::format_count(self.results.len()),
+                search::format_count(task.scanned()),
+            );
+            if task.truncated() {
+                summary.push_str(" (limit reached)");
+            } else if task.is_cancelled() {
+                summary.push_str(" (stopped)");
+            }
+            ui.label(summary);
+        }
+        if let Some(report) = &self.report {
+            ui.label(format!("Replaced {} matches in {} files", search::format_count(report.replaced), report.files));
+            for (name, error) in &report.failed {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Failed to write {name}: {error}"));
+            }
+        }
+
+        let mut to_open = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for file_index in 0..self.results.len() {
+                // Untitled tabs sort after the files
+                if matches!(self.results[file_index].source, Source::Untitled(_))
+                    && file_index.checked_sub(1).is_none_or(|i| matches!(self.results[i].source, Source::File(_)))
+                {
+                    ui.separator();
+                    ui.strong("Untitled");
+                }
+                if let Some(jump) = self.show_file(ui, file_index, documents) {
+                    to_open = Some(jump);
+                }
+            }
+        });
+        self.reveal_current = false;
+        to_open
+    }
+
+    fn show_file(&mut self, ui: &mut Ui, file_index: usize, documents: &[Document]) -> Option<(Source, Range<usize>)> {
+        let file = &self.results[file_index];
+        let source = file.source.clone();
+        let (name, hover) = match &source {
+            Source::File(path) => (path.file_name().unwrap_or_default().to_string_lossy().to_string(), path.display().to_string()),
+            Source::Untitled(_) => (source_name(&source, documents), "Unsaved tab".to_string()),
+        };
+        let mut title = egui::RichText::new(format!("{} ({})", name, file.match_count));
+        if file.ignored {
+            title = title.color(ui.visuals().weak_text_color());
+        }
+        let keys: Vec<(usize, usize)> = file.results
+            .iter()
+            .flat_map(|result| (0..result.matches.len()).map(move |index| (result.line_number, index)))
+            .collect();
+        let included = keys.iter().filter(|(line, index)| self.is_included(&source, *line, *index)).count();
+
+        let mut clicked = None;
+        let mut toggled = Vec::new();
+        let id = ui.make_persistent_id(&source);
+        egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, true)
+            .show_header(ui, |ui| {
+                if self.show_replace {
+                    let mut all = included == keys.len();
+                    let checkbox = egui::Checkbox::without_text(&mut all).indeterminate(included > 0 && included < keys.len());
+                    if ui.add(checkbox).changed() {
+                        for (line, index) in keys {
+                            if all {
+                                self.excluded.remove(&(source.clone(), line, index));
+                            } else {
+                                self.excluded.insert((source.clone(), line, index));
+                            }
+                        }
+                    }
+                }
+                ui.label(title).on_hover_text(hover);
+            })
+            .body(|ui| {
+                let file = &self.results[file_index];
+                for result in &file.results {
+                    if !self.show_replace {
+                        // One row per line; clicking selects its first match
+                        let text = format!("{}: {}", result.line_number, result.line_content.trim());
+                        let current = self.is_current(&source, result.line_number, None);
+                        let response = ui.selectable_label(current, text);
+                        if current && self.reveal_current {
+                            response.scroll_to_me(None);
+                        }
+                        if response.clicked() {
+                            clicked = Some((result.line_number, 0, result.matches[0].chars.clone()));
+                        }
+                        continue;
+                    }
+                    for (index, found) in result.matches.iter().enumerate() {
+                        let mut ticked = self.is_included(&source, result.line_number, index);
+                        let replacement = self.replacement_for(&result.line_content, found.bytes.start).unwrap_or_default();
+                        let preview = diff_preview(ui, result.line_number, &result.line_content, found.bytes.clone(), &replacement);
+                        let current = self.is_current(&source, result.line_number, Some(index));
+                        ui.horizontal(|ui| {
+                            if ui.add(egui::Checkbox::without_text(&mut ticked)).changed() {
+                                toggled.push((result.line_number, index, ticked));
+                            }
+                            let response = ui.selectable_label(current, preview);
+                            if current && self.reveal_current {
+                                response.scroll_to_me(None);
+                            }
+                            if response.clicked() {
+                                clicked = Some((result.line_number, index, found.chars.clone()));
+                            }
+                        });
+                    }
+                }
+            });
+        for (line, index, ticked) in toggled {
+            if ticked {
+                self.excluded.remove(&(source.clone(), line, index));
+            } else {
+                self.excluded.insert((source.clone(), line, index));
+            }
+        }
+        let (line, index, chars) = clicked?;
+        self.current = Some((source.clone(), line, index));
+        Some((source, chars))
+    }
+}
+
+// The line with the match struck through in red, followed by its replacement in green
+fn diff_preview(ui: &Ui, line_number: usize, line: &str, range: Range<usize>, replacement: &str) -> LayoutJob {
+    let font_id = FontId::monospace(ui.style().text_styles[&egui::TextStyle::Body].size);
+    let color = ui.visuals().text_color();
+    let plain = TextFormat::simple(font_id.clone(), color);
+    let removed = TextFormat {
+        background: Color32::from_rgba_unmultiplied(220, 60, 60, 60),
+        strikethrough: egui::Stroke::new(1.0, color),
+        ..plain.clone()
+    };
+    let added = TextFormat {
+        background: Color32::from_rgba_unmultiplied(60, 180, 60, 60),
+        ..plain.clone()
+    };
+
+    // Leading indentation only takes up room
+    let start = line.len() - line.trim_start().len();
+    let start = start.min(range.start);
+    let mut job = LayoutJob::default();
+    job.append(&format!("{line_number}: "), 0.0, plain.clone());
+    job.append(&line[start..range.start], 0.0, plain.clone());
+    job.append(&line[range.clone()], 0.0, removed);
+    job.append(replacement, 0.0, added);
+    job.append(&line[range.end..], 0.0, plain);
+    job
+}
+
+// File name, or the tab an untitled buffer is in
+fn source_name(source: &Source, documents: &[Document]) -> String {
+    match source {
+        Source::File(path) => path.display().to_string(),
+        Source::Untitled(id) => match documents.iter().position(|doc| doc.id == *id) {
+            Some(index) => format!("Untitled (tab {})", index + 1),
+            None => "Untitled (closed)".to_string(),
+        },
+    }
+}
+
+// Paths of the same file can be spelled differently, e.g. relative to the workspace or absolute
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_without_results_goes_nowhere() {
+        let mut panel = SearchPanel::default();
+        assert!(panel.step(true).is_none());
+        assert!(panel.step(false).is_none());
+        assert!(panel.current.is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::history::Selection;
+use crate::line_ending::LineEnding;
+use crate::save;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An open tab as it is remembered between launches.
+#[derive(Serialize, Deserialize)]
+pub struct SessionDocument {
+    pub file_path: Option<PathBuf>,
+    // Unsaved contents: always kept for untitled tabs, and for files with changes
+    pub text: Option<String>,
+    // Kept in case it was picked with Reopen with Encoding; detected again if missing
+    #[serde(default)]
+    pub encoding: Option<String>,
+    #[serde(default)]
+    pub line_ending: Option<LineEnding>,
+    pub cursors: Vec<Selection>,
+    pub primary: usize,
+    pub scroll_offset: [f32; 2],
+}
+
+/// Open tabs and panel layout of a workspace, saved on exit and restored on the next launch.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub documents: Vec<SessionDocument>,
+    pub active_index: usize,
+    pub show_sidebar: bool,
+    pub show_preview: bool,
+    pub show_minimap: bool,
+    pub show_search: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            documents: Vec::new(),
+            active_index: 0,
+            show_sidebar: true,
+            show_preview: false,
+            show_minimap: true,
+            show_search: false,
+        }
+    }
+}
+
+impl Session {
+    /// The session last saved for these workspace folders, if any.
+    pub fn load(roots: &[PathBuf]) -> io::Result<Option<Self>> {
+        match Self::path(roots) {
+            Some(path) => Self::load_from(&path),
+            None => Ok(None),
+        }
+    }
+
+    fn load_from(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn save(&self, roots: &[PathBuf]) -> io::Result<()> {
+        let path = Self::path(roots)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "there is no per-user data folder to keep it in"))?;
+        self.save_to(&path)
+    }
+
+    fn save_to(&self, path: &Path) -> io::Result<()> {
+        // Fails on paths that aren't valid UTF-8, which JSON can't hold
+        let data = serde_json::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Holds the only copy of unsaved changes
+        save::write_atomic(path, false, |writer| writer.write_all(data.as_bytes()))
+    }
+
+    // One file per set of workspace folders in the user's data folder, named by a hash that stays
+    // the same across builds
+    pub fn path(roots: &[PathBuf]) -> Option<PathBuf> {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in roots.iter().flat_map(|root| key_bytes(root)) {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        Some(dirs::data_dir()?.join("clawpad").join("sessions").join(format!("{hash:016x}.json")))
+    }
+}
+
+fn key_bytes(root: &Path) -> Vec<u8> {
+    let mut bytes = root.to_string_lossy().into_owned().into_bytes();
+    // Separates the roots so ["a", "bc"] and ["ab", "c"] differ
+    bytes.push(0);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("clawpad-session-test-{}-{name}", std::process::id())).join("session.json")
+    }
+
+    fn tab(file_path: Option<PathBuf>, text: Option<&str>) -> SessionDocument {
+        SessionDocument {
+            file_path,
+            text: text.map(str::to_string),
+            encoding: Some("UTF-16LE".to_string()),
+            line_ending: Some(LineEnding::CrLf),
+            cursors: vec![(1, 4), (6, 6)],
+            primary: 1,
+            scroll_offset: [0.0, 120.5],
+        }
+    }
+
+    #[test]
+    fn sessions_round_trip() {
+        let path = temp_path("round-trip");
+        let session = Session {
+            documents: vec![tab(Some(PathBuf::from("/work/main.rs")), None), tab(None, Some("unsaved\ntext"))],
+            active_index: 1,
+            show_sidebar: false,
+            show_preview: true,
+            show_minimap: false,
+            show_search: true,
+        };
+        session.save_to(&path).unwrap();
+        let loaded = Session::load_from(&path).unwrap().unwrap();
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), serde_json::to_string(&session).unwrap());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_fields_get_defaults_and_missing_files_no_session() {
+        let path = temp_path("defaults");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, r#"{"active_index": 2}"#).unwrap();
+        let loaded = Session::load_from(&path).unwrap().unwrap();
+        assert!(loaded.documents.is_empty() && loaded.show_sidebar && !loaded.show_search);
+        fs::write(&path, "{").unwrap();
+        assert_eq!(Session::load_from(&path).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(Session::load_from(&path).unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_utf8_fail_the_save() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = temp_path("not-utf8");
+        let file = PathBuf::from(std::ffi::OsStr::from_bytes(b"/work/\xff.rs"));
+        let session = Session { documents: vec![tab(Some(file), None)], ..Default::default() };
+        assert_eq!(session.save_to(&path).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        assert!(!path.exists());
+    }
+}
@@ -1,13 +1,31 @@
+use crate::line_ending::LineEnding;
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io;
 use std::path::PathBuf;
 
+// Missing fields fall back to their defaults so older settings files keep loading
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Settings {
     pub font_size: f32,
     pub font_family: String,
     pub theme_dark: bool,
     pub transparency: f32,
+    pub search_max_depth: usize,
+    pub search_max_results: usize,
+    // Gitignore-style patterns hidden from the explorer and search
+    pub exclude_patterns: Vec<String>,
+    // List ignored files greyed out instead of hiding them
+    pub show_ignored: bool,
+    // Explorer folders left expanded, across all workspace folders
+    pub explorer_expanded: Vec<PathBuf>,
+    // Keep unsaved changes in the session on exit instead of asking to save them
+    pub hot_exit: bool,
+    // Copy the previous contents of a file to `file~` when saving over it
+    pub backup_on_save: bool,
+    // Line ending of new files; existing files keep their own
+    pub default_line_ending: LineEnding,
 }
 
 impl Default for Settings {
@@ -17,29 +35,39 @@ impl Default for Settings {
             font_family: "monospace".to_string(),
             theme_dark: true,
             transparency: 0.9,
+            search_max_depth: 32,
+            search_max_results: 5000,
+            exclude_patterns: vec![".git".to_string()],
+            show_ignored: false,
+            explorer_expanded: Vec::new(),
+            hot_exit: true,
+            backup_on_save: false,
+            default_line_ending: LineEnding::native(),
         }
     }
 }
 
 impl Settings {
-    pub fn load() -> Self {
-        let path = Self::path();
-        if let Ok(data) = fs::read_to_string(&path) {
-            if let Ok(settings) = serde_json::from_str(&data) {
-                return settings;
+    /// Reads the settings file, creating it with the defaults if there is none. A file that
+    /// can't be read or parsed is left alone for the user to fix.
+    pub fn load() -> io::Result<Self> {
+        match fs::read_to_string(Self::path()) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let default = Self::default();
+                default.save()?;
+                Ok(default)
             }
+            Err(error) => Err(error),
         }
-        let default = Self::default();
-        let _ = default.save();
-        default
     }
 
-    pub fn save(&self) -> Result<(), std::io::Error> {
-        let data = serde_json::to_string_pretty(self).unwrap();
+    pub fn save(&self) -> io::Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
         fs::write(Self::path(), data)
     }
 
-    fn path() -> PathBuf {
+    pub fn path() -> PathBuf {
         PathBuf::from("settings.json")
     }
 }
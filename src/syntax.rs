@@ -1,49 +1,430 @@
 use egui::text::LayoutJob;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::LinesWithEndings;
+use egui::Color32;
+use ropey::Rope;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use syntect::highlighting::{HighlightState, Highlighter, RangedHighlightIterator, ThemeSet};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+// A parser checkpoint is kept at every Nth line
+const CHECKPOINT_INTERVAL: usize = 16;
+// Gaps larger than this are highlighted by the background worker instead of the UI thread
+const SYNC_LINES: usize = 2000;
+// Lines the worker highlights between checks for newer edits
+const WORKER_CHUNK: usize = 1000;
+
+type Spans = Vec<(Color32, Range<usize>)>;
+
+#[derive(Clone, PartialEq)]
+struct LineState {
+    parse: ParseState,
+    highlight: HighlightState,
+}
+
+#[derive(Clone, Default)]
+struct LineCache {
+    // Parser state at the start of the line, only kept on checkpoint lines
+    state: Option<Box<LineState>>,
+    // Colored byte ranges of the line, kept for lines that have been on screen
+    spans: Option<Spans>,
+}
+
+/// Per-document syntax highlighting state.
+///
+/// Lines before `valid_lines` are known to be correct. After an edit, lines past the
+/// edited region keep their old (shifted) checkpoints; re-highlighting resumes at the
+/// edit and stops as soon as it reaches one of those checkpoints with an equal state.
+#[derive(Default)]
+pub struct HighlightCache {
+    key: Option<(String, String)>,
+    revision: u64,
+    lines: Vec<LineCache>,
+    valid_lines: usize,
+    // Stale lines in `dirty_end..stale_end` become valid again once the state converges
+    dirty_end: usize,
+    stale_end: usize,
+    requested: Option<(u64, usize)>,
+}
+
+impl HighlightCache {
+    /// Updates the cache for an edit that replaced `removed` line breaks after `start_line`
+    /// with `inserted` ones.
+    pub fn edit(&mut self, start_line: usize, removed: usize, inserted: usize) {
+        self.revision += 1;
+        if self.key.is_none() || start_line >= self.lines.len() {
+            return;
+        }
+
+        let shift = |line: usize| {
+            if line > start_line + removed {
+                line + inserted - removed
+            } else {
+                line.min(start_line + inserted + 1)
+            }
+        };
+        // The stale region must come from a single highlighting pass: either the valid lines
+        // past the edit, or what is left of an older stale region
+        if self.valid_lines > start_line + removed {
+            self.stale_end = shift(self.valid_lines);
+            self.dirty_end = start_line + inserted + 1;
+        } else if self.stale_end > start_line + removed {
+            self.stale_end = shift(self.stale_end);
+            self.dirty_end = shift(self.dirty_end.max(self.valid_lines)).max(start_line + inserted + 1);
+        } else {
+            self.stale_end = 0;
+        }
+        self.valid_lines = self.valid_lines.min(start_line);
+
+        let end = (start_line + 1 + removed).min(self.lines.len());
+        self.lines.splice(start_line + 1..end, std::iter::repeat_n(LineCache::default(), inserted));
+        self.lines[start_line].spans = None;
+    }
+
+    fn reset(&mut self, key: (String, String), line_count: usize, initial: LineState) {
+        self.key = Some(key);
+        self.lines = vec![LineCache::default(); line_count];
+        self.lines[0].state = Some(Box::new(initial));
+        self.valid_lines = 0;
+        self.dirty_end = 0;
+        self.stale_end = 0;
+        self.requested = None;
+    }
+
+    // Nearest checkpoint at or before `line`
+    fn checkpoint_before(&self, line: usize) -> (usize, LineState) {
+        let mut index = line.min(self.lines.len() - 1);
+        loop {
+            if let Some(state) = &self.lines[index].state {
+                return (index, (**state).clone());
+            }
+            index -= 1;
+        }
+    }
+
+    /// Records the result of highlighting `line` from `state` (its start state).
+    /// Returns true when the state matched a stale checkpoint, i.e. everything after is valid again.
+    fn store_line(&mut self, line: usize, state: Option<&LineState>, spans: Option<Spans>) -> bool {
+        if let Some(state) = state {
+            let converged = line >= self.dirty_end
+                && line < self.stale_end
+                && self.lines[line].state.as_deref() == Some(state);
+            if converged {
+                self.valid_lines = self.stale_end;
+                self.dirty_end = 0;
+                self.stale_end = 0;
+                return true;
+            }
+        }
+        let entry = &mut self.lines[line];
+        entry.state = state.map(|s| Box::new(s.clone()));
+        entry.spans = spans;
+        self.valid_lines = line + 1;
+        false
+    }
+
+    // Whether the start state of `line` should be captured while highlighting through it
+    fn wants_state(&self, line: usize) -> bool {
+        line.is_multiple_of(CHECKPOINT_INTERVAL) || self.lines[line].state.is_some()
+    }
+}
+
+struct Job {
+    cache: Arc<Mutex<HighlightCache>>,
+    rope: Rope,
+    revision: u64,
+    until: usize,
+}
 
 pub struct SyntaxHighlighter {
-    pub ps: SyntaxSet,
-    pub ts: ThemeSet,
+    pub ps: Arc<SyntaxSet>,
+    pub ts: Arc<ThemeSet>,
+    jobs: Sender<Job>,
+}
+
+impl Default for SyntaxHighlighter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SyntaxHighlighter {
     pub fn new() -> Self {
-        Self {
-            ps: SyntaxSet::load_defaults_newlines(),
-            ts: ThemeSet::load_defaults(),
-        }
+        let ps = Arc::new(SyntaxSet::load_defaults_newlines());
+        let ts = Arc::new(ThemeSet::load_defaults());
+        let (jobs, receiver) = mpsc::channel();
+        let (worker_ps, worker_ts) = (ps.clone(), ts.clone());
+        std::thread::Builder::new()
+            .name("highlighter".to_string())
+            .spawn(move || run_worker(&worker_ps, &worker_ts, receiver))
+            .expect("failed to spawn highlighter thread");
+        Self { ps, ts, jobs }
     }
 
-    pub fn highlight(&self, text: &str, language: &str, theme: &str) -> LayoutJob {
-        let syntax = self.ps.find_syntax_by_name(language)
+    fn syntax(&self, language: &str) -> &SyntaxReference {
+        self.ps.find_syntax_by_name(language)
             .or_else(|| self.ps.find_syntax_by_extension(language))
-            .unwrap_or_else(|| self.ps.find_syntax_plain_text());
-        
-        let mut h = HighlightLines::new(syntax, &self.ts.themes[theme]);
-        let mut job = LayoutJob::default();
-
-        for line in LinesWithEndings::from(text) {
-            let ranges: Vec<(Style, &str)> = h.highlight_line(line, &self.ps).unwrap();
-            for (style, range) in ranges {
-                let color = egui::Color32::from_rgb(
-                    style.foreground.r,
-                    style.foreground.g,
-                    style.foreground.b,
-                );
-                job.append(
-                    range,
-                    0.0,
-                    egui::TextFormat {
-                        font_id: egui::FontId::monospace(14.0),
-                        color,
-                        ..Default::default()
-                    },
-                );
-            }
-        }
-        job
+            .unwrap_or_else(|| self.ps.find_syntax_plain_text())
+    }
+
+    /// Highlights `lines` of the rope into one layout job per line, without line breaks.
+    ///
+    /// Only the lines between the last highlighted one and `lines` are parsed. When that gap
+    /// is large it is handed to the background worker and the lines are returned uncolored
+    /// until it catches up.
+    pub fn highlight_lines(
+        &self,
+        handle: &Arc<Mutex<HighlightCache>>,
+        rope: &Rope,
+        lines: Range<usize>,
+        language: &str,
+        theme_name: &str,
+        font_id: &egui::FontId,
+    ) -> Vec<LayoutJob> {
+        let theme = &self.ts.themes[theme_name];
+        let highlighter = Highlighter::new(theme);
+        let syntax = self.syntax(language);
+        let lines = lines.start.min(rope.len_lines())..lines.end.min(rope.len_lines());
+
+        let mut cache = handle.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (syntax.name.clone(), theme_name.to_string());
+        if cache.key.as_ref() != Some(&key) || cache.lines.len() != rope.len_lines() {
+            let initial = LineState {
+                parse: ParseState::new(syntax),
+                highlight: HighlightState::new(&highlighter, ScopeStack::new()),
+            };
+            cache.reset(key, rope.len_lines(), initial);
+        }
+
+        if lines.end > cache.valid_lines + SYNC_LINES {
+            let request = (cache.revision, lines.end);
+            if cache.requested.is_none_or(|(revision, until)| revision != request.0 || until < request.1) {
+                cache.requested = Some(request);
+                let _ = self.jobs.send(Job {
+                    cache: Arc::clone(handle),
+                    rope: rope.clone(),
+                    revision: request.0,
+                    until: request.1,
+                });
+            }
+        } else {
+            advance(&mut cache, &self.ps, &highlighter, rope, lines.end, &lines);
+        }
+        fill_spans(&mut cache, &self.ps, &highlighter, rope, &lines);
+
+        let default_color = theme.settings.foreground.map(to_color32).unwrap_or(Color32::GRAY);
+        lines.map(|line| {
+            let text: Cow<str> = rope.line(line).into();
+            let text = text.trim_end_matches(['\n', '\r']);
+            let spans = cache.lines[line].spans.as_ref().filter(|_| line < cache.valid_lines);
+            layout_job(text, spans, default_color, font_id)
+        }).collect()
+    }
+}
+
+fn to_color32(color: syntect::highlighting::Color) -> Color32 {
+    Color32::from_rgb(color.r, color.g, color.b)
+}
+
+fn layout_job(text: &str, spans: Option<&Spans>, default_color: Color32, font_id: &egui::FontId) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    let format = |color| egui::TextFormat {
+        font_id: font_id.clone(),
+        color,
+        ..Default::default()
+    };
+    match spans {
+        Some(spans) => {
+            for (color, range) in spans {
+                job.append(&text[range.clone()], 0.0, format(*color));
+            }
+        }
+        None => job.append(text, 0.0, format(default_color)),
+    }
+    job
+}
+
+fn highlight_line(ps: &SyntaxSet, highlighter: &Highlighter, state: &mut LineState, line: &str) -> Spans {
+    let ops = state.parse.parse_line(line, ps).unwrap_or_default();
+    let text_len = line.trim_end_matches(['\n', '\r']).len();
+    RangedHighlightIterator::new(&mut state.highlight, &ops, line, highlighter)
+        .filter_map(|(style, _, range)| {
+            let range = range.start..range.end.min(text_len);
+            (!range.is_empty()).then(|| (to_color32(style.foreground), range))
+        })
+        .collect()
+}
+
+// State at the start of `cache.valid_lines`, replayed from the nearest trusted checkpoint
+fn resume_state(cache: &HighlightCache, ps: &SyntaxSet, highlighter: &Highlighter, rope: &Rope) -> LineState {
+    let (mut line, mut state) = cache.checkpoint_before(cache.valid_lines.saturating_sub(1));
+    while line < cache.valid_lines {
+        highlight_line(ps, highlighter, &mut state, &Cow::from(rope.line(line)));
+        line += 1;
+    }
+    state
+}
+
+// Highlights on the calling thread until `until`, keeping spans for the lines in `keep`
+fn advance(cache: &mut HighlightCache, ps: &SyntaxSet, highlighter: &Highlighter, rope: &Rope, until: usize, keep: &Range<usize>) {
+    'restart: while cache.valid_lines < until {
+        let mut state = resume_state(cache, ps, highlighter, rope);
+        for line in cache.valid_lines..until {
+            let snapshot = cache.wants_state(line).then(|| state.clone());
+            let spans = highlight_line(ps, highlighter, &mut state, &Cow::from(rope.line(line)));
+            if cache.store_line(line, snapshot.as_ref(), keep.contains(&line).then_some(spans)) {
+                continue 'restart;
+            }
+        }
+    }
+}
+
+// Visible lines that are valid but were highlighted off-screen (or by the worker) have no spans yet
+fn fill_spans(cache: &mut HighlightCache, ps: &SyntaxSet, highlighter: &Highlighter, rope: &Rope, lines: &Range<usize>) {
+    let end = lines.end.min(cache.valid_lines);
+    let Some(first_missing) = (lines.start..end).find(|&line| cache.lines[line].spans.is_none()) else {
+        return;
+    };
+    let (mut line, mut state) = cache.checkpoint_before(first_missing);
+    while line < end {
+        let spans = highlight_line(ps, highlighter, &mut state, &Cow::from(rope.line(line)));
+        if line >= first_missing {
+            cache.lines[line].spans.get_or_insert(spans);
+        }
+        line += 1;
+    }
+}
+
+fn run_worker(ps: &SyntaxSet, ts: &ThemeSet, receiver: Receiver<Job>) {
+    while let Ok(job) = receiver.recv() {
+        // Only the newest request per document matters
+        let mut jobs = vec![job];
+        while let Ok(job) = receiver.try_recv() {
+            jobs.retain(|j| !Arc::ptr_eq(&j.cache, &job.cache));
+            jobs.push(job);
+        }
+        for job in jobs {
+            highlight_in_background(ps, ts, &job);
+        }
+    }
+}
+
+fn highlight_in_background(ps: &SyntaxSet, ts: &ThemeSet, job: &Job) {
+    let lock = || job.cache.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        // Snapshot where to resume, then parse a chunk without holding the lock
+        let (start, mut state, wants_state, theme) = {
+            let cache = lock();
+            let Some((_, theme_name)) = &cache.key else { return };
+            if cache.revision != job.revision || cache.valid_lines >= job.until {
+                return;
+            }
+            let Some(theme) = ts.themes.get(theme_name) else { return };
+            let start = cache.valid_lines;
+            let end = (start + WORKER_CHUNK).min(job.until);
+            let wants_state: Vec<bool> = (start..end).map(|line| cache.wants_state(line)).collect();
+            let state = resume_state(&cache, ps, &Highlighter::new(theme), &job.rope);
+            (start, state, wants_state, theme)
+        };
+
+        let highlighter = Highlighter::new(theme);
+        let mut results = Vec::with_capacity(wants_state.len());
+        for (offset, wants) in wants_state.into_iter().enumerate() {
+            let line = start + offset;
+            let snapshot = wants.then(|| state.clone());
+            highlight_line(ps, &highlighter, &mut state, &Cow::from(job.rope.line(line)));
+            results.push(snapshot);
+        }
+
+        let mut cache = lock();
+        if cache.revision != job.revision || cache.valid_lines != start {
+            continue;
+        }
+        for (offset, snapshot) in results.iter().enumerate() {
+            if cache.store_line(start + offset, snapshot.as_ref(), None) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    fn cache(line_count: usize, valid_lines: usize) -> HighlightCache {
+        HighlightCache {
+            key: Some(("Rust".to_string(), "base16-ocean.dark".to_string())),
+            lines: vec![LineCache::default(); line_count],
+            valid_lines,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn edit_before_highlighting_only_bumps_the_revision() {
+        let mut cache = HighlightCache::default();
+        cache.edit(0, 0, 2);
+        assert_eq!(cache.revision, 1);
+        assert!(cache.lines.is_empty());
+    }
+
+    #[test]
+    fn edit_past_the_last_line_is_ignored() {
+        let mut cache = cache(3, 3);
+        cache.edit(5, 1, 0);
+        assert_eq!((cache.lines.len(), cache.valid_lines), (3, 3));
+    }
+
+    #[test]
+    fn edit_inserting_lines_shifts_the_stale_region() {
+        let mut cache = cache(10, 10);
+        cache.lines[2].spans = Some(Vec::new());
+        cache.edit(2, 0, 3);
+        assert_eq!(cache.lines.len(), 13);
+        assert_eq!((cache.valid_lines, cache.dirty_end, cache.stale_end), (2, 6, 13));
+        assert!(cache.lines[2].spans.is_none());
+    }
+
+    #[test]
+    fn edit_removing_lines_shrinks_the_cache() {
+        let mut cache = cache(10, 10);
+        cache.edit(2, 3, 0);
+        assert_eq!(cache.lines.len(), 7);
+        assert_eq!((cache.valid_lines, cache.dirty_end, cache.stale_end), (2, 3, 7));
+    }
+
+    #[test]
+    fn edit_removing_more_lines_than_there_are_stops_at_the_end() {
+        let mut cache = cache(5, 5);
+        cache.edit(3, 10, 0);
+        assert_eq!((cache.lines.len(), cache.valid_lines, cache.stale_end), (4, 3, 0));
+    }
+
+    #[test]
+    fn highlighting_after_edits_matches_highlighting_from_scratch() {
+        let highlighter = SyntaxHighlighter::new();
+        let font_id = egui::FontId::monospace(14.0);
+        let colors = |doc: &Document, cache: &Arc<Mutex<HighlightCache>>| -> Vec<Vec<(Color32, Range<usize>)>> {
+            let lines = 0..doc.rope.len_lines();
+            highlighter
+                .highlight_lines(cache, &doc.rope, lines, "Rust", "base16-ocean.dark", &font_id)
+                .into_iter()
+                .map(|job| job.sections.iter().map(|section| (section.format.color, section.byte_range.clone())).collect())
+                .collect()
+        };
+        let text: String = (0..100).map(|i| format!("let x{i} = \"{i}\"; // line {i}\n")).collect();
+        let mut doc = Document::new(text, None);
+        colors(&doc, &doc.highlight);
+
+        // Opening a comment changes every line after it, closing it brings the old states back
+        doc.insert(doc.rope.line_to_char(10), "/*\n");
+        assert_eq!(colors(&doc, &doc.highlight), colors(&doc, &Arc::default()));
+        doc.insert(doc.rope.line_to_char(50), "*/\n");
+        assert_eq!(colors(&doc, &doc.highlight), colors(&doc, &Arc::default()));
+        doc.delete(doc.rope.line_to_char(10)..doc.rope.line_to_char(11));
+        assert_eq!(colors(&doc, &doc.highlight), colors(&doc, &Arc::default()));
     }
 }
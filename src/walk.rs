@@ -0,0 +1,284 @@
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// Rules that apply to the whole workspace: global git excludes and the exclude list from settings.
+pub struct IgnoreRules {
+    global: Gitignore,
+    excludes: Gitignore,
+}
+
+impl IgnoreRules {
+    pub fn new(root: &Path, excludes: &[String]) -> Self {
+        let (global, _) = Gitignore::global();
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in excludes {
+            let _ = builder.add_line(None, pattern);
+        }
+        Self {
+            global,
+            excludes: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+        }
+    }
+
+    // Settings excludes hide entries outright, even when ignored files are shown
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.excludes.matched(path, is_dir).is_ignore()
+    }
+}
+
+#[derive(Clone)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    // Matched by a .gitignore, .ignore or global git exclude, or inside an ignored directory
+    pub ignored: bool,
+}
+
+/// Depth-first walk in file name order that honors ignore files along the way.
+pub struct Walk<'a> {
+    rules: &'a IgnoreRules,
+    max_depth: usize,
+    include_ignored: bool,
+    skip_dir: Box<dyn FnMut(&Path) -> bool + 'a>,
+    stack: Vec<Frame>,
+}
+
+// A directory being walked, with the ignore files found in it
+struct Frame {
+    entries: std::vec::IntoIter<(PathBuf, bool)>,
+    // Highest precedence first: .ignore, .gitignore, .git/info/exclude
+    matchers: Vec<Gitignore>,
+    ignored: bool,
+}
+
+impl<'a> Walk<'a> {
+    /// Walks below `root` up to `max_depth` levels, yielding ignored entries only if `include_ignored`.
+    pub fn new(root: &Path, rules: &'a IgnoreRules, max_depth: usize, include_ignored: bool) -> Self {
+        let mut walk = Self {
+            rules,
+            max_depth,
+            include_ignored,
+            skip_dir: Box::new(|_| false),
+            stack: Vec::new(),
+        };
+        if max_depth > 0 {
+            walk.stack.push(Frame::read(root, false));
+        }
+        walk
+    }
+
+    /// Lists the entries of `dir` only, honoring the ignore files from `root` down to it.
+    pub fn dir(root: &Path, dir: &Path, rules: &'a IgnoreRules, include_ignored: bool) -> Self {
+        let mut walk = Self::new(root, rules, 0, include_ignored);
+        walk.stack.push(Frame::matchers(root, false));
+        let mut path = root.to_path_buf();
+        for component in dir.strip_prefix(root).unwrap_or(dir).components() {
+            path.push(component);
+            // Nothing below a settings exclude is listed
+            if rules.is_excluded(&path, true) {
+                walk.stack.clear();
+                return walk;
+            }
+            let ignored = walk.stack.last().is_some_and(|frame| frame.ignored) || walk.is_ignored(&path, true);
+            walk.stack.push(Frame::matchers(&path, ignored));
+        }
+        if let Some(frame) = walk.stack.pop() {
+            walk.stack.push(Frame::read(&path, frame.ignored));
+        }
+        walk.max_depth = walk.stack.len();
+        walk
+    }
+
+    /// Walks everything below `dir`, honoring the ignore files from `root` down to it.
+    pub fn below(root: &Path, dir: &Path, rules: &'a IgnoreRules, include_ignored: bool) -> Self {
+        let mut walk = Self::dir(root, dir, rules, include_ignored);
+        walk.max_depth = usize::MAX;
+        walk
+    }
+
+    /// Doesn't descend into directories for which `skip` returns true.
+    pub fn skip_dirs(mut self, skip: impl FnMut(&Path) -> bool + 'a) -> Self {
+        self.skip_dir = Box::new(skip);
+        self
+    }
+
+    // The deepest ignore file with an opinion wins, then the global excludes
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for frame in self.stack.iter().rev() {
+            for matcher in &frame.matchers {
+                match matcher.matched(path, is_dir) {
+                    Match::None => {}
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                }
+            }
+        }
+        self.rules.global.matched(path, is_dir).is_ignore()
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = WalkEntry;
+
+    fn next(&mut self) -> Option<WalkEntry> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let parent_ignored = frame.ignored;
+            let Some((path, is_dir)) = frame.entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            if self.rules.is_excluded(&path, is_dir) {
+                continue;
+            }
+            let ignored = parent_ignored || self.is_ignored(&path, is_dir);
+            if ignored && !self.include_ignored {
+                continue;
+            }
+            if is_dir && self.stack.len() < self.max_depth && !(self.skip_dir)(&path) {
+                self.stack.push(Frame::read(&path, ignored));
+            }
+            return Some(WalkEntry { path, is_dir, ignored });
+        }
+    }
+}
+
+impl Frame {
+    fn read(dir: &Path, ignored: bool) -> Self {
+        let mut entries: Vec<(PathBuf, bool)> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| (entry.path(), entry.file_type().is_ok_and(|t| t.is_dir())))
+            .collect();
+        entries.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name()));
+        Self { entries: entries.into_iter(), ..Self::matchers(dir, ignored) }
+    }
+
+    // A directory's ignore files, without listing it
+    fn matchers(dir: &Path, ignored: bool) -> Self {
+        // Rules inside an ignored directory can't bring its entries back
+        let mut matchers = Vec::new();
+        if !ignored {
+            for file in [dir.join(".ignore"), dir.join(".gitignore"), dir.join(".git/info/exclude")] {
+                if file.is_file() {
+                    let mut builder = GitignoreBuilder::new(dir);
+                    builder.add(file);
+                    if let Ok(matcher) = builder.build() {
+                        matchers.push(matcher);
+                    }
+                }
+            }
+        }
+        Self { entries: Vec::new().into_iter(), matchers, ignored }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A fresh folder holding `files`, each created with its parent folders
+    fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("clawpad-walk-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    fn walk(root: &Path, walk: Walk) -> Vec<(String, bool)> {
+        walk.map(|entry| (entry.path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"), entry.ignored))
+            .collect()
+    }
+
+    fn shown(entries: &[(&str, bool)]) -> Vec<(String, bool)> {
+        entries.iter().map(|&(path, ignored)| (path.to_string(), ignored)).collect()
+    }
+
+    #[test]
+    fn gitignore_hides_matches_only() {
+        let root = tree("gitignore", &[
+            (".gitignore", "target/\n*.log\n"),
+            ("target/out.o", ""),
+            ("targets.rs", ""),
+            ("src/a.log", ""),
+            ("src/main.rs", ""),
+        ]);
+        let rules = IgnoreRules::new(&root, &[]);
+        assert_eq!(walk(&root, Walk::new(&root, &rules, usize::MAX, false)), shown(&[
+            (".gitignore", false),
+            ("src", false),
+            ("src/main.rs", false),
+            ("targets.rs", false),
+        ]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignored_entries_are_marked_when_included() {
+        let root = tree("include", &[(".gitignore", "target/\n"), ("target/debug/out.o", ""), ("main.rs", "")]);
+        let rules = IgnoreRules::new(&root, &[]);
+        assert_eq!(walk(&root, Walk::new(&root, &rules, usize::MAX, true)), shown(&[
+            (".gitignore", false),
+            ("main.rs", false),
+            ("target", true),
+            ("target/debug", true),
+            ("target/debug/out.o", true),
+        ]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn deeper_ignore_files_and_ignore_over_gitignore_win() {
+        let root = tree("precedence", &[
+            (".gitignore", "*.log\n!x.txt\n"),
+            (".ignore", "x.txt\n"),
+            ("x.txt", ""),
+            ("src/.gitignore", "!keep.log\n"),
+            ("src/keep.log", ""),
+            ("src/drop.log", ""),
+        ]);
+        let rules = IgnoreRules::new(&root, &[]);
+        assert_eq!(walk(&root, Walk::new(&root, &rules, usize::MAX, false)), shown(&[
+            (".gitignore", false),
+            (".ignore", false),
+            ("src", false),
+            ("src/.gitignore", false),
+            ("src/keep.log", false),
+        ]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn settings_excludes_stay_hidden_when_ignored_entries_are_shown() {
+        let root = tree("excludes", &[("node_modules/a/index.js", ""), ("index.js", "")]);
+        let rules = IgnoreRules::new(&root, &["node_modules".to_string()]);
+        assert_eq!(walk(&root, Walk::new(&root, &rules, usize::MAX, true)), shown(&[("index.js", false)]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dir_applies_the_ignore_files_above_it() {
+        let root = tree("dir", &[(".gitignore", "*.log\nbuild/\n"), ("src/a.log", ""), ("src/a.rs", ""), ("build/b.rs", "")]);
+        let rules = IgnoreRules::new(&root, &[]);
+        assert_eq!(walk(&root, Walk::dir(&root, &root.join("src"), &rules, false)), shown(&[("src/a.rs", false)]));
+        assert_eq!(walk(&root, Walk::dir(&root, &root.join("build"), &rules, true)), shown(&[("build/b.rs", true)]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn max_depth_and_skip_dirs_stop_descending() {
+        let root = tree("depth", &[("a/b/c.txt", ""), ("d/e.txt", "")]);
+        let rules = IgnoreRules::new(&root, &[]);
+        assert_eq!(walk(&root, Walk::new(&root, &rules, 1, false)), shown(&[("a", false), ("d", false)]));
+        let skip_d = Walk::new(&root, &rules, usize::MAX, false).skip_dirs(|dir| dir.ends_with("d"));
+        assert_eq!(walk(&root, skip_d), shown(&[("a", false), ("a/b", false), ("a/b/c.txt", false), ("d", false)]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
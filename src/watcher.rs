@@ -0,0 +1,241 @@
+use crate::encoding::{self, Decoded, TextEncoding};
+use crate::walk::{IgnoreRules, Walk};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// What the watcher thread has to report, in the order it happened.
+pub enum Update {
+    /// Absolute paths created, modified or removed.
+    Changed(BTreeSet<PathBuf>),
+    /// A file read for `FileWatcher::read`, decoded in `encoding`.
+    Read { path: PathBuf, encoding: TextEncoding, result: io::Result<Decoded> },
+}
+
+// Work for the watcher thread
+enum Message {
+    Event(notify::Result<Event>),
+    SetRoots(Vec<PathBuf>, Vec<String>),
+    WatchFile(PathBuf),
+    Read(PathBuf, TextEncoding),
+}
+
+/// Watches the workspace folders, plus open files outside them, for changes made by other programs.
+///
+/// Walking the folders to watch and reading changed files happen on a thread of its own, so large
+/// workspaces and files don't stall the UI.
+pub struct FileWatcher {
+    messages: Sender<Message>,
+    updates: Receiver<Update>,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        let (messages, inbox) = mpsc::channel();
+        let (outbox, updates) = mpsc::channel();
+        let events = messages.clone();
+        let worker = Worker {
+            // None if the platform watcher couldn't be started; the app then works without it
+            watcher: notify::recommended_watcher(move |event| {
+                let _ = events.send(Message::Event(event));
+            })
+            .ok(),
+            roots: Vec::new(),
+            tree_dirs: HashSet::new(),
+            dirs: HashSet::new(),
+            updates: outbox,
+        };
+        std::thread::Builder::new()
+            .name("file watcher".to_string())
+            .spawn(move || worker.run(inbox))
+            .expect("failed to spawn file watcher thread");
+        Self { messages, updates }
+    }
+
+    /// Watches these workspace folders instead of the previous ones, or the same ones with new
+    /// exclude patterns.
+    pub fn set_roots(&mut self, roots: &[PathBuf], excludes: &[String]) {
+        let roots = roots.iter().map(|root| std::path::absolute(root).unwrap_or_else(|_| root.clone())).collect();
+        let _ = self.messages.send(Message::SetRoots(roots, excludes.to_vec()));
+    }
+
+    /// Also watches `path` if it isn't inside a workspace folder.
+    pub fn watch_file(&mut self, path: &Path) {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let _ = self.messages.send(Message::WatchFile(path));
+    }
+
+    /// Reads `path` in `encoding` on the watcher thread; the text comes back as an `Update::Read`.
+    pub fn read(&mut self, path: &Path, encoding: TextEncoding) {
+        let _ = self.messages.send(Message::Read(path.to_path_buf(), encoding));
+    }
+
+    /// What happened since the last call.
+    pub fn updates(&mut self) -> Vec<Update> {
+        self.updates.try_iter().collect()
+    }
+}
+
+// The watcher thread's state
+struct Worker {
+    watcher: Option<RecommendedWatcher>,
+    // Each workspace folder with its exclude patterns
+    roots: Vec<(PathBuf, IgnoreRules)>,
+    // Folders watched in the roots: all but ignored and excluded ones, like build output and
+    // dependencies, which change a lot and aren't listed anyway. Each is watched on its own,
+    // since a recursive watch can't leave any out.
+    tree_dirs: HashSet<PathBuf>,
+    // Folders of open files outside those
+    dirs: HashSet<PathBuf>,
+    updates: Sender<Update>,
+}
+
+impl Worker {
+    fn run(mut self, inbox: Receiver<Message>) {
+        while let Ok(first) = inbox.recv() {
+            // Everything queued is taken at once, so a burst of events reads each file only once
+            let mut changed = BTreeSet::new();
+            let mut reads = Vec::new();
+            for message in std::iter::once(first).chain(inbox.try_iter()) {
+                match message {
+                    // Reads show up as access events on some platforms
+                    Message::Event(Ok(event)) if !matches!(event.kind, EventKind::Access(_)) => changed.extend(event.paths),
+                    Message::Event(_) => {}
+                    Message::SetRoots(roots, excludes) => self.set_roots(roots, &excludes),
+                    Message::WatchFile(path) => self.watch_file(&path),
+                    Message::Read(path, encoding) => {
+                        if !reads.contains(&(path.clone(), encoding)) {
+                            reads.push((path, encoding));
+                        }
+                    }
+                }
+            }
+            if !changed.is_empty() {
+                self.rewatch(&changed);
+                if self.updates.send(Update::Changed(changed)).is_err() {
+                    return;
+                }
+            }
+            for (path, encoding) in reads {
+                let result = encoding::read_as(&path, encoding);
+                if self.updates.send(Update::Read { path, encoding, result }).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn set_roots(&mut self, roots: Vec<PathBuf>, excludes: &[String]) {
+        self.roots = roots.into_iter()
+            .map(|root| {
+                let rules = IgnoreRules::new(&root, excludes);
+                (root, rules)
+            })
+            .collect();
+        let wanted: HashSet<PathBuf> = self.roots.iter().flat_map(|(root, rules)| tree_dirs(root, root, rules)).collect();
+        self.replace_tree_dirs(None, wanted);
+    }
+
+    // Watches the folders in `wanted` instead of the watched ones in `subtree`, or all of them
+    fn replace_tree_dirs(&mut self, subtree: Option<&Path>, wanted: HashSet<PathBuf>) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        self.tree_dirs.retain(|dir| {
+            let keep = subtree.is_some_and(|subtree| !dir.starts_with(subtree)) || wanted.contains(dir);
+            if !keep {
+                let _ = watcher.unwatch(dir);
+            }
+            keep
+        });
+        for dir in wanted {
+            if !self.tree_dirs.contains(&dir) && watcher.watch(&dir, RecursiveMode::NonRecursive).is_ok() {
+                self.tree_dirs.insert(dir);
+            }
+        }
+    }
+
+    fn watch_file(&mut self, path: &Path) {
+        // Its folder is watched, since saving by replacing the file would drop a watch on the file itself
+        let Some(dir) = path.parent() else {
+            return;
+        };
+        if self.tree_dirs.contains(dir) || self.dirs.contains(dir) {
+            return;
+        }
+        if let Some(watcher) = &mut self.watcher
+            && watcher.watch(dir, RecursiveMode::NonRecursive).is_ok()
+        {
+            self.dirs.insert(dir.to_path_buf());
+        }
+    }
+
+    // New folders need watches of their own, and an edited ignore file can change which of the
+    // folders below it do; only those subtrees are walked again
+    fn rewatch(&mut self, changed: &BTreeSet<PathBuf>) {
+        for path in changed {
+            if !path.exists() {
+                // A removed folder's watch is gone, so one made again in its place needs a new one
+                self.tree_dirs.remove(path);
+                continue;
+            }
+            let Some((root, rules)) = self.roots.iter().find(|(root, _)| path.starts_with(root)) else {
+                continue;
+            };
+            let ignore_file = path.file_name().is_some_and(|name| name == ".gitignore" || name == ".ignore");
+            let subtree = match path.parent() {
+                Some(dir) if ignore_file && self.tree_dirs.contains(dir) => dir,
+                _ if path.is_dir() && !self.tree_dirs.contains(path) => path,
+                _ => continue,
+            };
+            let wanted = tree_dirs(root, subtree, rules).into_iter().collect();
+            self.replace_tree_dirs(Some(subtree), wanted);
+        }
+    }
+}
+
+// `dir` and the folders below it that aren't ignored or excluded, or nothing if `dir` itself is
+fn tree_dirs(root: &Path, dir: &Path, rules: &IgnoreRules) -> Vec<PathBuf> {
+    let listed = dir == root
+        || dir.parent().is_some_and(|parent| Walk::dir(root, parent, rules, false).any(|entry| entry.is_dir && entry.path == dir));
+    if !listed {
+        return Vec::new();
+    }
+    std::iter::once(dir.to_path_buf())
+        .chain(Walk::below(root, dir, rules, false).filter(|entry| entry.is_dir).map(|entry| entry.path))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn tree_dirs_leave_out_ignored_and_excluded_folders() {
+        let root = std::env::temp_dir().join(format!("clawpad-watcher-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["src/deep", "target/debug", "node_modules/a", "src/build"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join(".gitignore"), "target/\nbuild/\n").unwrap();
+        let rules = IgnoreRules::new(&root, &["node_modules".to_string()]);
+
+        let mut all = tree_dirs(&root, &root, &rules);
+        all.sort();
+        assert_eq!(all, [root.clone(), root.join("src"), root.join("src/deep")]);
+        assert_eq!(tree_dirs(&root, &root.join("src/deep"), &rules), [root.join("src/deep")]);
+        for left_out in ["target", "target/debug", "node_modules/a", "src/build"] {
+            assert!(tree_dirs(&root, &root.join(left_out), &rules).is_empty(), "{left_out}");
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+}